@@ -1,9 +1,554 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+/// Name of an app, validated so that `basedir.join(name)` always stays a
+/// direct child of the state dir
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct AppName(String);
+
+impl AppName {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for AppName {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let problem = if name.is_empty() {
+            Some("must not be empty")
+        } else if name.len() > 255 {
+            Some("must be at most 255 bytes long")
+        } else if name.contains('/') || name.contains('\\') {
+            Some("must not contain path separators")
+        } else if name.contains("..") {
+            Some("must not contain '..'")
+        } else if name.starts_with('.') {
+            Some("must not start with a dot")
+        } else if name.chars().any(char::is_control) {
+            Some("must not contain control characters")
+        } else {
+            None
+        };
+        match problem {
+            Some(problem) => Err(format!("invalid app name {:?}: {}", name, problem)),
+            None => Ok(AppName(name.to_string())),
+        }
+    }
+}
+
+impl TryFrom<String> for AppName {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        name.parse()
+    }
+}
+
+impl From<AppName> for String {
+    fn from(name: AppName) -> Self {
+        name.0
+    }
+}
+
+impl fmt::Display for AppName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<Path> for AppName {
+    fn as_ref(&self) -> &Path {
+        Path::new(&self.0)
+    }
+}
+
+/// Who started a run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Origin {
+    #[default]
+    Cli,
+    Api,
+    /// started again by the supervisor's restart policy
+    #[value(skip)]
+    Restart,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Origin::Cli => "cli",
+            Origin::Api => "api",
+            Origin::Restart => "restart",
+        })
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LastRunInfo {
     pub time: String,
-    pub pid: u32
+    pub pid: u32,
+    /// start time of `pid` from /proc/<pid>/stat, used to detect pid reuse
+    #[serde(default)]
+    pub start_time: Option<u64>,
+    #[serde(default)]
+    pub started_by: Origin,
+    /// exit code of start.sh, `None` while running or when killed by a signal
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    /// signal that terminated start.sh
+    #[serde(default)]
+    pub signal: Option<i32>,
+    #[serde(default)]
+    pub ended_at: Option<String>,
+}
+
+impl LastRunInfo {
+    /// Short description of how the run ended, e.g. `0`, `1` or `SIGKILL`
+    pub fn exit_status(&self) -> Option<String> {
+        match (self.exit_code, self.signal) {
+            (Some(code), _) => Some(code.to_string()),
+            (None, Some(signal)) => Some(signal_name(signal)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// One line of `history.jsonl`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub run: u64,
+    pub started_at: String,
+    pub pid: u32,
+    pub started_by: Origin,
+    pub ended_at: Option<String>,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub duration_secs: Option<i64>,
+}
+
+impl RunRecord {
+    pub fn new(run: u64, last_run: &LastRunInfo) -> Self {
+        let duration_secs = last_run.ended_at.as_ref().and_then(|ended| {
+            let start: DateTime<Local> = last_run.time.parse().ok()?;
+            let end: DateTime<Local> = ended.parse().ok()?;
+            Some((end - start).num_seconds())
+        });
+        RunRecord {
+            run,
+            started_at: last_run.time.clone(),
+            pid: last_run.pid,
+            started_by: last_run.started_by,
+            ended_at: last_run.ended_at.clone(),
+            exit_code: last_run.exit_code,
+            signal: last_run.signal,
+            duration_secs,
+        }
+    }
+
+    pub fn exit_status(&self) -> Option<String> {
+        match (self.exit_code, self.signal) {
+            (Some(code), _) => Some(code.to_string()),
+            (None, Some(signal)) => Some(signal_name(signal)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Signals known by name, without the `SIG` prefix. `signal_name` and
+/// `parse_signal` both go through this table, so any name written to app.toml
+/// parses back.
+pub const SIGNALS: &[(i32, &str)] = &[
+    (libc::SIGHUP, "HUP"),
+    (libc::SIGINT, "INT"),
+    (libc::SIGQUIT, "QUIT"),
+    (libc::SIGABRT, "ABRT"),
+    (libc::SIGKILL, "KILL"),
+    (libc::SIGUSR1, "USR1"),
+    (libc::SIGSEGV, "SEGV"),
+    (libc::SIGUSR2, "USR2"),
+    (libc::SIGPIPE, "PIPE"),
+    (libc::SIGALRM, "ALRM"),
+    (libc::SIGTERM, "TERM"),
+];
+
+/// `SIGTERM` for the signals of `SIGNALS`, `signal <number>` for the others
+pub fn signal_name(signal: i32) -> String {
+    match SIGNALS.iter().find(|(number, _)| *number == signal) {
+        Some((_, name)) => format!("SIG{}", name),
+        None => format!("signal {}", signal),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartMode {
+    /// never restart (default)
+    #[default]
+    Never,
+    /// restart only when the app exits unsuccessfully
+    OnFailure,
+    /// restart whenever the app exits
+    Always,
+}
+
+impl fmt::Display for RestartMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RestartMode::Never => "never",
+            RestartMode::OnFailure => "on-failure",
+            RestartMode::Always => "always",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RestartPolicy {
+    pub mode: RestartMode,
+    /// give up after this many consecutive restarts (0 = retry forever)
+    pub max_retries: u32,
+    /// delay before the first restart, doubled on every consecutive retry
+    pub backoff_secs: u64,
+    pub max_backoff_secs: u64,
+    /// a run that stays up at least this long resets the retry counter
+    pub reset_after_secs: u64,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            mode: RestartMode::Never,
+            max_retries: 5,
+            backoff_secs: 1,
+            max_backoff_secs: 60,
+            reset_after_secs: 60,
+        }
+    }
+}
+
+impl RestartPolicy {
+    pub fn should_restart(&self, success: bool, retries: u32) -> bool {
+        let wanted = match self.mode {
+            RestartMode::Never => false,
+            RestartMode::OnFailure => !success,
+            RestartMode::Always => true,
+        };
+        wanted && self.can_retry(retries)
+    }
+
+    /// whether `max_retries` allows another restart
+    pub fn can_retry(&self, retries: u32) -> bool {
+        self.max_retries == 0 || retries < self.max_retries
+    }
+
+    /// delay before retry number `retries + 1`
+    pub fn backoff(&self, retries: u32) -> u64 {
+        self.backoff_secs
+            .saturating_mul(1u64.checked_shl(retries).unwrap_or(u64::MAX))
+            .min(self.max_backoff_secs)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RestartState {
+    /// consecutive restarts since the last manual run
+    pub count: u32,
+    pub next_retry_at: Option<String>,
+    /// set by stop/kill so the supervisor does not bring the app back
+    pub stop_requested: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSettings {
+    /// number of previous runs whose logs are archived in logs/runs (0 = truncate on every run)
+    pub keep_runs: u32,
+    /// rotate stdout.log/stderr.log once they grow past this many bytes (0 = never)
+    pub max_size: u64,
+    /// rotated segments kept per log file
+    pub max_files: u32,
+    /// gzip rotated segments
+    pub compress: bool,
+    /// prefix every captured line with an RFC3339 timestamp
+    pub timestamps: bool,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        LogSettings {
+            keep_runs: 0,
+            max_size: 0,
+            max_files: 5,
+            compress: true,
+            timestamps: false,
+        }
+    }
+}
+
+impl LogSettings {
+    /// Whether execmgr has to sit between the app and its log files
+    pub fn capture(&self) -> bool {
+        self.max_size > 0 || self.timestamps
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StopSettings {
+    /// run stop.sh first when it exists
+    pub use_script: bool,
+    /// signal sent when stop.sh is missing or did not stop the app
+    pub signal: String,
+    /// seconds to wait for each stage before escalating
+    pub timeout_secs: u64,
+    /// also signal descendants found in /proc that left the app's process group
+    pub descendants: bool,
+}
+
+impl Default for StopSettings {
+    fn default() -> Self {
+        StopSettings {
+            use_script: true,
+            signal: "SIGTERM".into(),
+            timeout_secs: 10,
+            descendants: false,
+        }
+    }
+}
+
+/// What a health check probes, written as `cmd:<shell command>`,
+/// `tcp:[host:]port` or `http://host[:port]/path`. IPv6 hosts are written in
+/// brackets, e.g. `tcp:[::1]:8080`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum HealthProbe {
+    /// run through `sh -c` in the app's working dir and environment, healthy
+    /// on exit code 0
+    Command(String),
+    /// `host` without brackets
+    Tcp { host: String, port: u16 },
+    /// plain http only, healthy when the status matches `HealthCheck::status`
+    Http(String),
+}
+
+impl FromStr for HealthProbe {
+    type Err = String;
+
+    fn from_str(probe: &str) -> Result<Self, Self::Err> {
+        if let Some(command) = probe.strip_prefix("cmd:") {
+            if command.trim().is_empty() {
+                return Err("empty health check command".into());
+            }
+            return Ok(HealthProbe::Command(command.to_string()));
+        }
+        if let Some(address) = probe.strip_prefix("tcp:") {
+            let (host, port) = match split_host_port(address) {
+                Some((host, Some(port))) => (host, port),
+                Some((port, None)) if !address.starts_with('[') => ("127.0.0.1", port),
+                _ => return Err(format!("invalid address in health check {:?}", probe)),
+            };
+            let port = port
+                .parse()
+                .map_err(|_| format!("invalid port in health check {:?}", probe))?;
+            return Ok(HealthProbe::Tcp {
+                host: host.to_string(),
+                port,
+            });
+        }
+        if let Some(rest) = probe.strip_prefix("http://") {
+            if rest.is_empty() || rest.starts_with('/') {
+                return Err(format!("missing host in health check {:?}", probe));
+            }
+            return Ok(HealthProbe::Http(probe.to_string()));
+        }
+        Err(format!(
+            "invalid health check {:?}: expected cmd:<command>, tcp:[host:]port or http://host[:port]/path",
+            probe
+        ))
+    }
+}
+
+impl TryFrom<String> for HealthProbe {
+    type Error = String;
+
+    fn try_from(probe: String) -> Result<Self, Self::Error> {
+        probe.parse()
+    }
+}
+
+impl From<HealthProbe> for String {
+    fn from(probe: HealthProbe) -> Self {
+        probe.to_string()
+    }
+}
+
+impl fmt::Display for HealthProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthProbe::Command(command) => write!(f, "cmd:{}", command),
+            HealthProbe::Tcp { host, port } if host.contains(':') => {
+                write!(f, "tcp:[{}]:{}", host, port)
+            }
+            HealthProbe::Tcp { host, port } => write!(f, "tcp:{}:{}", host, port),
+            HealthProbe::Http(url) => f.write_str(url),
+        }
+    }
+}
+
+/// Splits `host[:port]` or `[ipv6 host][:port]` into the host, without
+/// brackets, and the port. `None` for an IPv6 host without brackets.
+pub fn split_host_port(address: &str) -> Option<(&str, Option<&str>)> {
+    if let Some(rest) = address.strip_prefix('[') {
+        let (host, rest) = rest.split_once(']')?;
+        return match rest {
+            "" => Some((host, None)),
+            rest => Some((host, Some(rest.strip_prefix(':')?))),
+        };
+    }
+    match address.split_once(':') {
+        Some((_, port)) if port.contains(':') => None,
+        Some((host, port)) => Some((host, Some(port))),
+        None => Some((address, None)),
+    }
+}
+
+/// What `run --wait-ready` waits for: `log:<regex>` matching a line of the
+/// current run's output, or any health probe
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ReadyProbe {
+    Log(String),
+    Check(HealthProbe),
+}
+
+impl FromStr for ReadyProbe {
+    type Err = String;
+
+    fn from_str(probe: &str) -> Result<Self, Self::Err> {
+        match probe.strip_prefix("log:") {
+            Some(pattern) => {
+                Regex::new(pattern).map_err(|e| format!("invalid readiness regex: {}", e))?;
+                Ok(ReadyProbe::Log(pattern.to_string()))
+            }
+            None => probe.parse().map(ReadyProbe::Check).map_err(|_| {
+                format!(
+                    "invalid readiness probe {:?}: expected log:<regex>, cmd:<command>, tcp:[host:]port or http://host[:port]/path",
+                    probe
+                )
+            }),
+        }
+    }
+}
+
+impl TryFrom<String> for ReadyProbe {
+    type Error = String;
+
+    fn try_from(probe: String) -> Result<Self, Self::Error> {
+        probe.parse()
+    }
+}
+
+impl From<ReadyProbe> for String {
+    fn from(probe: ReadyProbe) -> Self {
+        probe.to_string()
+    }
+}
+
+impl fmt::Display for ReadyProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadyProbe::Log(pattern) => write!(f, "log:{}", pattern),
+            ReadyProbe::Check(probe) => probe.fmt(f),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReadyCheck {
+    pub probe: Option<ReadyProbe>,
+    /// how long `run --wait-ready` waits by default
+    pub timeout_secs: u64,
+}
+
+impl Default for ReadyCheck {
+    fn default() -> Self {
+        ReadyCheck {
+            probe: None,
+            timeout_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthCheck {
+    /// no health checks when unset
+    pub probe: Option<HealthProbe>,
+    /// expected status of an http probe
+    pub status: u16,
+    pub interval_secs: u64,
+    pub timeout_secs: u64,
+    /// consecutive failures before the app is unhealthy
+    pub threshold: u32,
+    /// stop an unhealthy app so the supervisor starts it again
+    pub restart: bool,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        HealthCheck {
+            probe: None,
+            status: 200,
+            interval_secs: 10,
+            timeout_secs: 5,
+            threshold: 3,
+            restart: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Health {
+    /// no probe succeeded yet in this run
+    #[default]
+    Starting,
+    Healthy,
+    Unhealthy,
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Health::Starting => "starting",
+            Health::Healthy => "healthy",
+            Health::Unhealthy => "unhealthy",
+        })
+    }
+}
+
+/// Result of the health checks of the current run, reset on every start
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthState {
+    pub status: Health,
+    /// consecutive failed probes
+    pub failures: u32,
+    pub checked_at: Option<String>,
+    /// why the last probe failed
+    pub last_error: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -12,4 +557,103 @@ pub struct App {
     pub created_at: String,
     pub last_run: Option<LastRunInfo>,
     pub num_runs: u64,
-}
\ No newline at end of file
+    #[serde(default)]
+    pub restarts: RestartState,
+    #[serde(default)]
+    pub health_state: HealthState,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn app_name_accepts_plain_names() {
+        for name in ["web", "my-app_2", "api.v2", "a b"] {
+            assert_eq!(name.parse::<AppName>().unwrap().as_str(), name);
+        }
+    }
+
+    #[test]
+    fn app_name_rejects_paths_and_control_characters() {
+        let long = "a".repeat(256);
+        for name in [
+            "", "..", "../web", "a..b", "a/b", "/web", "a\\b", ".", ".hidden", "a\nb", "a\tb",
+            "a\0b", &long,
+        ] {
+            assert!(name.parse::<AppName>().is_err(), "{:?} was accepted", name);
+        }
+        assert!("a".repeat(255).parse::<AppName>().is_ok());
+    }
+
+    #[test]
+    fn health_probe_parses_each_kind() {
+        assert_eq!(
+            "cmd:pg_isready -p 5433".parse(),
+            Ok(HealthProbe::Command("pg_isready -p 5433".into()))
+        );
+        assert_eq!(
+            "tcp:5432".parse(),
+            Ok(HealthProbe::Tcp {
+                host: "127.0.0.1".into(),
+                port: 5432
+            })
+        );
+        assert_eq!(
+            "tcp:db.local:80".parse(),
+            Ok(HealthProbe::Tcp {
+                host: "db.local".into(),
+                port: 80
+            })
+        );
+        assert_eq!(
+            "tcp:[::1]:8080".parse(),
+            Ok(HealthProbe::Tcp {
+                host: "::1".into(),
+                port: 8080
+            })
+        );
+        assert_eq!(
+            "http://localhost:8000/health".parse(),
+            Ok(HealthProbe::Http("http://localhost:8000/health".into()))
+        );
+    }
+
+    #[test]
+    fn health_probe_rejects_invalid_probes() {
+        for probe in [
+            "",
+            "cmd:",
+            "cmd:   ",
+            "tcp:",
+            "tcp:http",
+            "tcp:70000",
+            "tcp:db:",
+            "tcp:::1:8080",
+            "tcp:[::1]",
+            "tcp:[::1]8080",
+            "http://",
+            "http:///health",
+            "https://localhost",
+            "localhost:80",
+        ] {
+            assert!(
+                probe.parse::<HealthProbe>().is_err(),
+                "{:?} was accepted",
+                probe
+            );
+        }
+    }
+
+    #[test]
+    fn health_probe_round_trips_through_display() {
+        for probe in [
+            "cmd:true",
+            "tcp:127.0.0.1:5432",
+            "tcp:[::1]:5432",
+            "http://localhost/health",
+        ] {
+            assert_eq!(probe.parse::<HealthProbe>().unwrap().to_string(), probe);
+        }
+    }
+}
@@ -1,13 +1,30 @@
-use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use chrono::{DateTime, Local};
+use clap::{Parser, Subcommand, ValueEnum};
+
+use execmgr::utils::parse_time;
+use execmgr::{AppName, Origin, Settings};
 
 #[derive(Parser)]
 #[command(name = "execmgr")]
 #[command(about = "Execution manager for local apps/services")]
 pub struct Cli {
+    /// Output format: aligned tables, tab-separated rows without headers, or JSON
+    #[arg(short = 'o', long, global = true, value_enum, default_value_t = Output::Table)]
+    pub output: Output,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Output {
+    Table,
+    Plain,
+    Json,
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     /// show app info
@@ -15,7 +32,12 @@ pub enum Commands {
     Info,
 
     /// Create a new app or service
-    Create { name: String },
+    Create {
+        name: AppName,
+        /// Run this command directly instead of start.sh, e.g. `-- python3 -m http.server`
+        #[arg(last = true, value_name = "COMMAND")]
+        command: Vec<String>,
+    },
     /// List apps
     #[command(alias = "ls")]
     List {
@@ -38,33 +60,130 @@ pub enum Commands {
     /// Run an app
     #[command(alias = "start")]
     Run { 
-        name: String,
+        name: AppName,
         /// no logs in output
         #[arg(short = 'd', long)]
         detached: bool,
+        /// Block until the readiness probe passes (see `set --ready`), optionally with a timeout in seconds
+        #[arg(long, value_name = "SECS", num_args = 0..=1, require_equals = true)]
+        wait_ready: Option<Option<u64>>,
+        /// Don't start the apps of depends_on first
+        #[arg(long)]
+        no_deps: bool,
     },
 
     /// View Status of an app
-    Status { name: String },
+    Status { name: AppName },
 
-    /// Stop an app using stop.sh
-    Stop { 
-        name: String,
+    /// Stop an app: stop.sh, then the stop signal, then SIGKILL
+    Stop {
+        name: AppName,
 
         /// force run stop.sh
         #[arg(short = 'f', long)]
         force: bool,
+
+        /// Seconds to wait for each stage before escalating
+        #[arg(short = 't', long)]
+        timeout: Option<u64>,
+
+        /// Skip stop.sh and go straight to signals
+        #[arg(long, conflicts_with = "force")]
+        no_script: bool,
+
+        /// Also signal descendants that left the app's process group
+        #[arg(long)]
+        tree: bool,
+
+        /// Stop the running apps that depend on it first
+        #[arg(long)]
+        cascade: bool,
+    },
+
+    /// Kill the process group of an app with SIGKILL
+    Kill {
+        name: AppName,
+
+        /// Also kill descendants that left the app's process group
+        #[arg(long)]
+        tree: bool,
+    },
+
+    /// Configure the restart policy and log settings of an app
+    Set {
+        name: AppName,
+
+        #[command(flatten)]
+        settings: Box<Settings>,
+    },
+
+    /// Show the environment an app is started with, secrets masked
+    Env {
+        name: AppName,
+
+        /// Show the values of passwords, tokens and keys
+        #[arg(long)]
+        reveal: bool,
+    },
+
+    /// Run and watch an app (used internally by run)
+    #[command(hide = true)]
+    Supervise {
+        name: AppName,
+
+        #[arg(long, value_enum, default_value_t = Origin::Cli)]
+        origin: Origin,
+    },
+
+    /// Serve the HTTP API of the web UI
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8000")]
+        bind: String,
+
+        /// Also serve the built frontend (e.g. execmge-api/frontend/dist) from this dir
+        #[arg(long)]
+        static_dir: Option<PathBuf>,
+
+        /// Also accept browser requests from this origin, e.g. the frontend dev
+        /// server at http://localhost:8080 (repeatable)
+        #[arg(long = "allow-origin", value_name = "ORIGIN")]
+        allow_origins: Vec<String>,
     },
 
-    /// Kill an app using pid
-    Kill { name: String },
+    /// Serve the JSON control protocol on a Unix socket
+    Listen {
+        /// Socket path, defaults to execmgr.sock in the state dir
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    },
+
+    /// Serve the control socket and supervise every app run through it
+    Daemon {
+        /// Socket path, defaults to execmgr.sock in the state dir
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    },
+
+    /// Show the run history of an app
+    History {
+        name: AppName,
+
+        /// Only show the most recent N runs
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+
+        /// Print the runs as JSON (same as `--output json`)
+        #[arg(long)]
+        json: bool,
+    },
 
     /// Delete an app
     #[command(alias = "rm")]
     Delete {
-        name: String,
+        name: AppName,
 
-        /// Skip confirmation
+        /// Skip confirmation, and delete even if other apps depend on it
         #[arg(short = 'f', long)]
         force: bool,
     },
@@ -72,7 +191,7 @@ pub enum Commands {
     /// see an app logs
     Log {
         /// App name
-        name: String,
+        name: AppName,
 
         /// Clear logs instead of showing
         #[arg(short = 'c', long)]
@@ -89,5 +208,21 @@ pub enum Commands {
         /// Do not follow logs, just dump current logs
         #[arg(short = 'd', long, conflicts_with = "clear")]
         no_follow: bool,
+
+        /// Dump the logs of an earlier run (see `set --keep-runs`)
+        #[arg(short = 'r', long, conflicts_with = "clear")]
+        run: Option<u64>,
+
+        /// Only show lines logged at or after this time, e.g. 10m, 2h or "2026-01-15 23:30:00"
+        #[arg(long, value_parser = parse_time, conflicts_with = "clear")]
+        since: Option<DateTime<Local>>,
+
+        /// Only show lines logged at or before this time (implies --no-follow)
+        #[arg(long, value_parser = parse_time, conflicts_with = "clear")]
+        until: Option<DateTime<Local>>,
+
+        /// Interleave stdout and stderr in the order they were logged (needs timestamps)
+        #[arg(short = 'm', long, conflicts_with_all = ["clear", "stdout", "stderr"])]
+        merged: bool,
     },
 }
@@ -0,0 +1,580 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use toml_edit::{DocumentMut, Item, Table};
+
+use crate::app::{AppName, HealthCheck, LogSettings, ReadyCheck, RestartPolicy, StopSettings};
+use crate::error::Error;
+use crate::utils::parse_signal;
+
+/// Name of the config file in every app dir
+pub const CONFIG_FILE: &str = "app.toml";
+
+/// Written by `create`, every value is the default
+pub const DEFAULT_CONFIG: &str = r#"# Settings of this app, read by every execmgr command.
+# Runtime state (runs, pids, restarts) is kept in app.json.
+# `execmgr set` edits this file and keeps your comments.
+
+# Program and arguments executed directly instead of start.sh
+# command = ["python3", "-m", "http.server", "8000"]
+
+# Working directory of start.sh, stop.sh, the command and cmd: probes,
+# relative to the app dir (the default) or absolute, e.g. a project checkout
+# cwd = "~/src/project"
+
+# Labels for your own bookkeeping
+tags = []
+
+# Apps `execmgr run` starts first, waiting until they are ready if they
+# have a [ready] probe. `execmgr stop` warns about running dependents.
+depends_on = []
+
+# .env files (KEY=value lines) loaded in order, relative to the app dir
+env_files = []
+
+# Start from an empty environment instead of the one execmgr runs in,
+# keeping only the variables listed in pass_env
+clean_env = false
+pass_env = ["PATH", "HOME", "USER", "LOGNAME", "SHELL", "LANG", "LC_ALL", "TERM", "TZ"]
+
+# Variables set for start.sh, stop.sh or the command, override the .env files
+[env]
+# RUST_LOG = "info"
+
+[restart]
+# never, on-failure or always
+mode = "never"
+# give up after this many consecutive restarts (0 = retry forever)
+max_retries = 5
+# delay before the first restart, doubled on every consecutive retry
+backoff_secs = 1
+max_backoff_secs = 60
+# a run that stays up this long resets the retry counter
+reset_after_secs = 60
+
+[stop]
+# run stop.sh first when it exists
+use_script = true
+# signal sent when stop.sh is missing or did not stop the app
+signal = "SIGTERM"
+# seconds to wait for each stage before escalating to SIGKILL
+timeout_secs = 10
+# also signal descendants that left the app's process group
+descendants = false
+
+[logs]
+# logs of previous runs kept in logs/runs (0 = truncate on every run)
+keep_runs = 0
+# rotate stdout.log/stderr.log once they grow past this many bytes (0 = never)
+max_size = 0
+max_files = 5
+compress = true
+# prefix every line with an RFC3339 timestamp
+timestamps = false
+
+[health]
+# cmd:<command>, tcp:[host:]port or http://host[:port]/path
+# probe = "tcp:8080"
+# expected status of an http probe
+status = 200
+interval_secs = 10
+timeout_secs = 5
+# consecutive failures before the app is unhealthy
+threshold = 3
+# restart the app once it is unhealthy
+restart = false
+
+[ready]
+# what `run --wait-ready` waits for: log:<regex> or any health probe
+# probe = "log:listening on"
+timeout_secs = 60
+"#;
+
+/// Contents of app.toml: everything about an app the user configures
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    /// argv executed instead of start.sh
+    pub command: Option<Vec<String>>,
+    /// working directory, relative to the app dir
+    pub cwd: Option<PathBuf>,
+    pub tags: Vec<String>,
+    /// apps started before this one
+    pub depends_on: Vec<AppName>,
+    /// .env files, relative to the app dir
+    pub env_files: Vec<PathBuf>,
+    /// only pass the variables in `pass_env` through from execmgr's environment
+    pub clean_env: bool,
+    pub pass_env: Vec<String>,
+    pub env: BTreeMap<String, String>,
+    pub restart: RestartPolicy,
+    pub stop: StopSettings,
+    pub logs: LogSettings,
+    pub health: HealthCheck,
+    pub ready: ReadyCheck,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            command: None,
+            cwd: None,
+            tags: Vec::new(),
+            depends_on: Vec::new(),
+            env_files: Vec::new(),
+            clean_env: false,
+            pass_env: [
+                "PATH", "HOME", "USER", "LOGNAME", "SHELL", "LANG", "LC_ALL", "TERM", "TZ",
+            ]
+            .map(String::from)
+            .to_vec(),
+            env: BTreeMap::new(),
+            restart: RestartPolicy::default(),
+            stop: StopSettings::default(),
+            logs: LogSettings::default(),
+            health: HealthCheck::default(),
+            ready: ReadyCheck::default(),
+        }
+    }
+}
+
+/// A setting that parses but makes no sense, `key` is its path in the file
+#[derive(Debug)]
+pub struct Invalid {
+    pub key: Vec<String>,
+    pub message: String,
+}
+
+impl Invalid {
+    fn new(key: &[&str], message: impl Into<String>) -> Self {
+        Invalid {
+            key: key.iter().map(|key| key.to_string()).collect(),
+            message: message.into(),
+        }
+    }
+}
+
+impl AppConfig {
+    /// Directory the app's processes run in: `cwd`, or the app dir itself
+    pub fn work_dir(&self, app_dir: &Path) -> PathBuf {
+        match &self.cwd {
+            Some(cwd) => match cwd.strip_prefix("~") {
+                Ok(rest) => match std::env::var_os("HOME") {
+                    Some(home) => Path::new(&home).join(rest),
+                    None => app_dir.join(cwd),
+                },
+                Err(_) => app_dir.join(cwd),
+            },
+            None => app_dir.to_path_buf(),
+        }
+    }
+
+    /// Checks what the types alone cannot express
+    pub fn validate(&self) -> Result<(), Invalid> {
+        if let Some(command) = &self.command
+            && command.first().is_none_or(|program| program.is_empty())
+        {
+            return Err(Invalid::new(&["command"], "command must not be empty"));
+        }
+        if self.cwd.as_ref().is_some_and(|cwd| cwd.as_os_str().is_empty()) {
+            return Err(Invalid::new(&["cwd"], "cwd must not be empty"));
+        }
+        for key in self.env.keys() {
+            if !valid_env_name(key) {
+                return Err(Invalid::new(
+                    &["env", key],
+                    format!("invalid environment variable name {:?}", key),
+                ));
+            }
+        }
+        if let Some(name) = self.pass_env.iter().find(|name| !valid_env_name(name)) {
+            return Err(Invalid::new(
+                &["pass_env"],
+                format!("invalid environment variable name {:?}", name),
+            ));
+        }
+        if self.env_files.iter().any(|file| file.as_os_str().is_empty()) {
+            return Err(Invalid::new(&["env_files"], "env file path must not be empty"));
+        }
+        if self.restart.max_backoff_secs < self.restart.backoff_secs {
+            return Err(Invalid::new(
+                &["restart", "max_backoff_secs"],
+                format!(
+                    "max backoff ({}s) is smaller than backoff ({}s)",
+                    self.restart.max_backoff_secs, self.restart.backoff_secs
+                ),
+            ));
+        }
+        if let Err(e) = parse_signal(&self.stop.signal) {
+            return Err(Invalid::new(&["stop", "signal"], e));
+        }
+        if self.health.interval_secs == 0 {
+            return Err(Invalid::new(
+                &["health", "interval_secs"],
+                "health interval must be at least 1s",
+            ));
+        }
+        if self.health.timeout_secs == 0 {
+            return Err(Invalid::new(
+                &["health", "timeout_secs"],
+                "health timeout must be at least 1s",
+            ));
+        }
+        if self.health.threshold == 0 {
+            return Err(Invalid::new(
+                &["health", "threshold"],
+                "health threshold must be at least 1",
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub fn valid_env_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains('=') && !name.contains('\0')
+}
+
+/// app.toml of a new app, a command takes the place of the commented example
+pub fn initial_config(command: Option<&[String]>) -> String {
+    let Some(command) = command else {
+        return DEFAULT_CONFIG.to_string();
+    };
+    let command: toml_edit::Array = command.iter().collect();
+    DEFAULT_CONFIG
+        .lines()
+        .map(|line| match line.strip_prefix("# command = ") {
+            Some(_) => format!("command = {}", command),
+            None => line.to_string(),
+        })
+        .map(|line| line + "\n")
+        .collect()
+}
+
+/// Dependencies of the app named `name` with settings `config`, transitively
+/// and in start order: every app comes after the ones it depends on. Cycles
+/// and unknown apps are reported at the `depends_on` that introduces them.
+pub fn dependency_order(
+    base_dir: &Path,
+    name: &AppName,
+    config: &AppConfig,
+) -> Result<Vec<AppName>, Error> {
+    let mut order = Vec::new();
+    visit_dependencies(base_dir, name, config, &mut Vec::new(), &mut order)?;
+    // the app itself comes last
+    order.pop();
+    Ok(order)
+}
+
+/// Walks `depends_on` depth first
+fn visit_dependencies(
+    base_dir: &Path,
+    name: &AppName,
+    config: &AppConfig,
+    chain: &mut Vec<AppName>,
+    order: &mut Vec<AppName>,
+) -> Result<(), Error> {
+    chain.push(name.clone());
+    for dependency in &config.depends_on {
+        if let Some(start) = chain.iter().position(|app| app == dependency) {
+            let cycle: Vec<&str> = chain[start..]
+                .iter()
+                .chain([dependency])
+                .map(AppName::as_str)
+                .collect();
+            let message = format!("dependency cycle: {}", cycle.join(" -> "));
+            return Err(dependency_error(&base_dir.join(name.as_str()), &message));
+        }
+        if order.contains(dependency) {
+            continue;
+        }
+        let app_dir = base_dir.join(dependency.as_str());
+        if !app_dir.is_dir() {
+            let message = format!("unknown app '{}' in depends_on", dependency);
+            return Err(dependency_error(&base_dir.join(name.as_str()), &message));
+        }
+        let config = read_config(&app_dir)?;
+        visit_dependencies(base_dir, dependency, &config, chain, order)?;
+    }
+    chain.pop();
+    order.push(name.clone());
+    Ok(())
+}
+
+/// Error pointing at `depends_on` in the app.toml of `app_dir`
+fn dependency_error(app_dir: &Path, message: &str) -> Error {
+    let path = app_dir.join(CONFIG_FILE);
+    let text = std::fs::read_to_string(&path).unwrap_or_default();
+    config_error(&path, &text, locate(&text, &["depends_on".into()]), message)
+}
+
+/// Reads and validates app.toml, without looking at other apps: `depends_on`
+/// is checked by `dependency_order`. Apps created before app.toml kept their
+/// settings in app.json, those are moved into a new app.toml on the first
+/// read, before a write of app.json drops them.
+pub fn read_config(app_dir: &Path) -> Result<AppConfig, Error> {
+    let path = app_dir.join(CONFIG_FILE);
+    match std::fs::read_to_string(&path) {
+        Ok(text) => parse_config(&path, &text),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let config = legacy_config(app_dir)?;
+            write_config(app_dir, &config)?;
+            Ok(config)
+        }
+        Err(e) => Err(Error::io(format!("unable to read {:?}", path), e)),
+    }
+}
+
+/// Parses the contents of `path`, errors point at the offending line
+pub fn parse_config(path: &Path, text: &str) -> Result<AppConfig, Error> {
+    let config: AppConfig = toml::from_str(text)
+        .map_err(|e| config_error(path, text, e.span(), e.message()))?;
+    config.validate().map_err(|invalid| {
+        config_error(path, text, locate(text, &invalid.key), &invalid.message)
+    })?;
+    Ok(config)
+}
+
+/// Writes `config` into app.toml, keeping the comments and layout of the
+/// current file (or of the default one)
+pub fn write_config(app_dir: &Path, config: &AppConfig) -> Result<(), Error> {
+    let path = app_dir.join(CONFIG_FILE);
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => DEFAULT_CONFIG.to_string(),
+        Err(e) => return Err(Error::io(format!("unable to read {:?}", path), e)),
+    };
+    let mut document: DocumentMut = text
+        .parse()
+        .map_err(|e: toml_edit::TomlError| config_error(&path, &text, e.span(), e.message()))?;
+    let new = to_document(config)?;
+    let default = to_document(&AppConfig::default())?;
+    merge(document.as_table_mut(), new.as_table(), Some(default.as_table()));
+
+    let tmp_path = app_dir.join("app.toml.tmp");
+    std::fs::write(&tmp_path, document.to_string())
+        .map_err(|e| Error::io(format!("failed to write {:?}", tmp_path), e))?;
+    std::fs::rename(&tmp_path, &path)
+        .map_err(|e| Error::io(format!("failed to write {:?}", path), e))
+}
+
+fn to_document(config: &AppConfig) -> Result<DocumentMut, Error> {
+    toml::to_string(config)
+        .map_err(|e| Error::Failed(format!("failed to dump config: {}", e)))?
+        .parse()
+        .map_err(|e| Error::Failed(format!("failed to dump config: {}", e)))
+}
+
+/// Copies the values of `new` into `document`. Keys missing from `document`
+/// are only added when they differ from `default`, keys missing from `new`
+/// (unset options) are removed.
+fn merge(document: &mut Table, new: &Table, default: Option<&Table>) {
+    let unset: Vec<String> = document
+        .iter()
+        .filter(|(key, _)| !new.contains_key(key))
+        .map(|(key, _)| key.to_string())
+        .collect();
+    for key in unset {
+        document.remove(&key);
+    }
+
+    for (key, item) in new.iter() {
+        let default = default.and_then(|default| default.get(key));
+        match item {
+            Item::Table(table) => match document.get_mut(key) {
+                Some(Item::Table(existing)) => {
+                    merge(existing, table, default.and_then(Item::as_table))
+                }
+                Some(existing) => *existing = item.clone(),
+                None => {
+                    let mut added = Table::new();
+                    merge(&mut added, table, default.and_then(Item::as_table));
+                    if !added.is_empty() {
+                        document.insert(key, Item::Table(added));
+                    }
+                }
+            },
+            Item::Value(value) => {
+                let same = |other: &Item| {
+                    other
+                        .as_value()
+                        .is_some_and(|other| other.to_string().trim() == value.to_string().trim())
+                };
+                match document.get_mut(key) {
+                    Some(existing) if same(existing) => {}
+                    Some(Item::Value(existing)) => {
+                        let decor = existing.decor().clone();
+                        *existing = value.clone();
+                        *existing.decor_mut() = decor;
+                    }
+                    Some(existing) => *existing = item.clone(),
+                    None if default.is_some_and(same) => {}
+                    None => {
+                        document.insert(key, item.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Span of `key` in `text`, or of the closest table containing it
+fn locate(text: &str, key: &[String]) -> Option<Range<usize>> {
+    let document = toml_edit::Document::parse(text).ok()?;
+    let mut table = document.as_table() as &dyn toml_edit::TableLike;
+    let mut span = None;
+    for part in key {
+        let item = table.get(part)?;
+        span = item.span().or(span);
+        match item.as_table_like() {
+            Some(inner) => table = inner,
+            None => break,
+        }
+    }
+    span
+}
+
+fn config_error(path: &Path, text: &str, span: Option<Range<usize>>, message: &str) -> Error {
+    let (line, column) = match span {
+        Some(span) => {
+            let before = &text[..span.start.min(text.len())];
+            let line = before.matches('\n').count() + 1;
+            let column = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+            (Some(line), Some(column))
+        }
+        None => (None, None),
+    };
+    Error::Config {
+        path: path.to_path_buf(),
+        line,
+        column,
+        message: message.trim().to_string(),
+    }
+}
+
+/// Apps created before app.toml kept their settings in app.json
+fn legacy_config(app_dir: &Path) -> Result<AppConfig, Error> {
+    #[derive(Default, Deserialize)]
+    #[serde(default)]
+    struct Legacy {
+        restart: RestartPolicy,
+        logs: LogSettings,
+        stop: StopSettings,
+        health: HealthCheck,
+        ready: ReadyCheck,
+    }
+
+    let json_path = app_dir.join("app.json");
+    let content = std::fs::read_to_string(&json_path)
+        .map_err(|e| Error::io(format!("unable to read {:?}", json_path), e))?;
+    let legacy: Legacy = serde_json::from_str(&content).map_err(|e| Error::CorruptMetadata {
+        path: json_path,
+        reason: e.to_string(),
+    })?;
+    Ok(AppConfig {
+        restart: legacy.restart,
+        logs: legacy.logs,
+        stop: legacy.stop,
+        health: legacy.health,
+        ready: legacy.ready,
+        ..AppConfig::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::temp_dir;
+
+    fn app(base_dir: &Path, name: &str, depends_on: &[&str]) -> AppConfig {
+        let app_dir = base_dir.join(name);
+        std::fs::create_dir_all(&app_dir).unwrap();
+        let text = format!("depends_on = {:?}\n", depends_on);
+        std::fs::write(app_dir.join(CONFIG_FILE), &text).unwrap();
+        parse_config(&app_dir.join(CONFIG_FILE), &text).unwrap()
+    }
+
+    fn names(order: Vec<AppName>) -> Vec<String> {
+        order.into_iter().map(String::from).collect()
+    }
+
+    #[test]
+    fn dependency_order_starts_dependencies_first() {
+        let base = temp_dir("dependency-order");
+        app(&base, "db", &[]);
+        app(&base, "cache", &["db"]);
+        app(&base, "api", &["cache", "db"]);
+        let web = app(&base, "web", &["api", "cache"]);
+        let order = dependency_order(&base, &"web".parse().unwrap(), &web).unwrap();
+        assert_eq!(names(order), ["db", "cache", "api"]);
+        std::fs::remove_dir_all(&base).ok();
+    }
+
+    #[test]
+    fn dependency_order_without_dependencies() {
+        let base = temp_dir("dependency-none");
+        let db = app(&base, "db", &[]);
+        assert!(
+            dependency_order(&base, &"db".parse().unwrap(), &db)
+                .unwrap()
+                .is_empty()
+        );
+        std::fs::remove_dir_all(&base).ok();
+    }
+
+    #[test]
+    fn dependency_order_reports_cycles_at_depends_on() {
+        let base = temp_dir("dependency-cycle");
+        app(&base, "a", &["b"]);
+        app(&base, "b", &["c"]);
+        let c = app(&base, "c", &["a"]);
+        match dependency_order(&base, &"c".parse().unwrap(), &c) {
+            Err(Error::Config {
+                path,
+                line,
+                message,
+                ..
+            }) => {
+                assert_eq!(message, "dependency cycle: c -> a -> b -> c");
+                assert_eq!(path, base.join("b").join(CONFIG_FILE));
+                assert_eq!(line, Some(1));
+            }
+            other => panic!("expected a cycle, got {:?}", other),
+        }
+        std::fs::remove_dir_all(&base).ok();
+    }
+
+    #[test]
+    fn dependency_order_reports_unknown_apps() {
+        let base = temp_dir("dependency-unknown");
+        app(&base, "db", &["missing"]);
+        let api = app(&base, "api", &["db"]);
+        match dependency_order(&base, &"api".parse().unwrap(), &api) {
+            Err(Error::Config { path, message, .. }) => {
+                assert_eq!(message, "unknown app 'missing' in depends_on");
+                assert_eq!(path, base.join("db").join(CONFIG_FILE));
+            }
+            other => panic!("expected an unknown app, got {:?}", other),
+        }
+        std::fs::remove_dir_all(&base).ok();
+    }
+
+    #[test]
+    fn read_config_leaves_depends_on_to_dependency_order() {
+        let base = temp_dir("dependency-read");
+        app(&base, "api", &["missing"]);
+        let api = read_config(&base.join("api")).unwrap();
+        assert_eq!(names(api.depends_on), ["missing"]);
+        std::fs::remove_dir_all(&base).ok();
+    }
+
+    #[test]
+    fn self_dependency_is_a_cycle() {
+        let base = temp_dir("dependency-self");
+        let db = app(&base, "db", &["db"]);
+        assert!(dependency_order(&base, &"db".parse().unwrap(), &db).is_err());
+        std::fs::remove_dir_all(&base).ok();
+    }
+}
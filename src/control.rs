@@ -0,0 +1,389 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Deserializer};
+use serde_json::{Value, json};
+
+use execmgr::utils::parse_time;
+use execmgr::{AppName, Error, LogQuery, Manager, Origin, RunOptions, Settings, StopOptions};
+
+/// How often subscriptions look for state changes
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Default socket of `listen`, in the state dir
+pub fn socket_path(manager: &Manager) -> PathBuf {
+    manager.basedir().join("execmgr.sock")
+}
+
+/// One line of the protocol: `{"command": "...", ...arguments}`, with an
+/// optional `id` echoed back in the response
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+enum Request {
+    Info,
+    Create {
+        name: AppName,
+        /// program and arguments to run directly, a start.sh app when empty
+        #[serde(default)]
+        argv: Vec<String>,
+    },
+    List,
+    Ps,
+    Run {
+        name: AppName,
+        /// recorded in the history, `api` unless the CLI forwards its own run
+        #[serde(default = "api")]
+        origin: Origin,
+        #[serde(default)]
+        no_deps: bool,
+    },
+    Status {
+        name: AppName,
+    },
+    Stop {
+        name: AppName,
+        #[serde(default)]
+        force: bool,
+        timeout: Option<u64>,
+        #[serde(default)]
+        no_script: bool,
+        #[serde(default)]
+        tree: bool,
+        #[serde(default)]
+        cascade: bool,
+    },
+    Kill {
+        name: AppName,
+        #[serde(default)]
+        tree: bool,
+    },
+    Set {
+        name: AppName,
+        #[serde(flatten)]
+        settings: Box<Settings>,
+    },
+    History {
+        name: AppName,
+        limit: Option<usize>,
+    },
+    Env {
+        name: AppName,
+        #[serde(default)]
+        reveal: bool,
+    },
+    Delete {
+        name: AppName,
+        #[serde(default)]
+        force: bool,
+    },
+    Log {
+        name: AppName,
+        #[serde(default)]
+        clear: bool,
+        #[serde(default)]
+        stdout: bool,
+        #[serde(default)]
+        stderr: bool,
+        run: Option<u64>,
+        #[serde(default, deserialize_with = "time")]
+        since: Option<DateTime<Local>>,
+        #[serde(default, deserialize_with = "time")]
+        until: Option<DateTime<Local>>,
+        #[serde(default)]
+        merged: bool,
+    },
+    /// Push an event whenever the state of one of `names` (default: every app) changes
+    Subscribe {
+        #[serde(default)]
+        names: Vec<AppName>,
+    },
+}
+
+fn api() -> Origin {
+    Origin::Api
+}
+
+/// `since`/`until` take the same formats as the CLI
+fn time<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Local>>, D::Error> {
+    let time = String::deserialize(deserializer)?;
+    parse_time(&time)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Entry point of `listen`: serves the protocol on `path` until killed, one
+/// thread per connection
+pub fn listen(manager: &Manager, path: &Path) -> Result<(), Error> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(Error::Failed(format!(
+                "{:?} is already served by another execmgr",
+                path
+            )));
+        }
+        // left behind by a listener that was killed
+        std::fs::remove_file(path)
+            .map_err(|e| Error::io(format!("failed to remove stale socket {:?}", path), e))?;
+    }
+    std::fs::create_dir_all(manager.basedir())
+        .map_err(|e| Error::io(format!("failed to create path {:?}", manager.basedir()), e))?;
+    let listener = UnixListener::bind(path)
+        .map_err(|e| Error::io(format!("failed to listen on {:?}", path), e))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+        .map_err(|e| Error::io(format!("failed to set permissions {:?}", path), e))?;
+    println!("listening on {}", path.display());
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        let manager = manager.clone();
+        thread::spawn(move || handle(&manager, stream));
+    }
+    Ok(())
+}
+
+/// Sends one request to whatever serves `path` (`listen` or `daemon`) and
+/// returns its result, `None` when nothing is listening
+pub fn request(path: &Path, request: &Value) -> Option<Result<Value, Error>> {
+    let mut stream = UnixStream::connect(path).ok()?;
+    let result = (|| {
+        writeln!(stream, "{}", request)?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        Ok::<_, std::io::Error>(line)
+    })();
+    let line = match result {
+        Ok(line) => line,
+        Err(e) => return Some(Err(Error::io(format!("failed to talk to {:?}", path), e))),
+    };
+    let mut response: Value = match serde_json::from_str(&line) {
+        Ok(response) => response,
+        Err(e) => {
+            return Some(Err(Error::Failed(format!(
+                "invalid response from {:?}: {}",
+                path, e
+            ))));
+        }
+    };
+    if response["ok"] == Value::Bool(true) {
+        return Some(Ok(response["result"].take()));
+    }
+    Some(Err(Error::Remote {
+        message: response["error"]["message"]
+            .as_str()
+            .unwrap_or("unknown error")
+            .to_string(),
+        code: response["error"]["code"].as_i64().unwrap_or(1) as i32,
+    }))
+}
+
+type Writer = Arc<Mutex<UnixStream>>;
+
+fn send(writer: &Writer, message: &Value) -> bool {
+    let mut stream = writer.lock().unwrap_or_else(|e| e.into_inner());
+    writeln!(stream, "{}", message).is_ok()
+}
+
+fn handle(manager: &Manager, stream: UnixStream) {
+    let writer: Writer = match stream.try_clone() {
+        Ok(stream) => Arc::new(Mutex::new(stream)),
+        Err(_) => return,
+    };
+    // stops the subscriptions of this connection once the client is gone
+    let closed = Arc::new(AtomicBool::new(false));
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (id, request) = match serde_json::from_str::<Value>(&line) {
+            Ok(value) => (
+                value.get("id").cloned().unwrap_or(Value::Null),
+                serde_json::from_value::<Request>(value),
+            ),
+            Err(e) => (Value::Null, Err(e)),
+        };
+        let response = match request {
+            Ok(Request::Subscribe { names }) => match manager.list() {
+                Ok(apps) => {
+                    let apps: Vec<_> = apps
+                        .into_iter()
+                        .filter(|app| {
+                            names.is_empty() || names.iter().any(|n| n.as_str() == app.name)
+                        })
+                        .collect();
+                    // changes are reported from this snapshot on, so none gets lost
+                    // between it and the first poll
+                    let known = apps
+                        .iter()
+                        .map(|app| (app.name.clone(), state(&json!(app))))
+                        .collect();
+                    if !send(&writer, &json!({ "id": id, "ok": true, "result": apps })) {
+                        break;
+                    }
+                    let (manager, writer, closed) =
+                        (manager.clone(), writer.clone(), closed.clone());
+                    thread::spawn(move || watch(&manager, &writer, &closed, names, known));
+                    continue;
+                }
+                Err(e) => Err(e),
+            },
+            Ok(request) => execute(manager, request)
+                .map(|result| json!({ "id": id, "ok": true, "result": result })),
+            Err(e) => Err(Error::Invalid(format!("invalid request: {}", e))),
+        };
+        let response = response.unwrap_or_else(|e| {
+            json!({
+                "id": id,
+                "ok": false,
+                "error": { "message": e.to_string(), "code": e.exit_code() },
+            })
+        });
+        if !send(&writer, &response) {
+            break;
+        }
+    }
+    closed.store(true, Ordering::Relaxed);
+}
+
+/// Runs one request, the result has the schema of `--output json`
+fn execute(manager: &Manager, request: Request) -> Result<Value, Error> {
+    let value = match request {
+        Request::Info => json!(manager.info()?),
+        Request::Create { name, argv } if argv.is_empty() => json!(manager.create(&name)?),
+        Request::Create { name, argv } => json!(manager.create_command(&name, argv)?),
+        Request::List => json!(manager.list()?),
+        Request::Ps => {
+            let apps: Vec<_> = manager
+                .list()?
+                .into_iter()
+                .filter(|status| status.running || status.pending_restart)
+                .collect();
+            json!(apps)
+        }
+        Request::Run {
+            name,
+            origin,
+            no_deps,
+        } => json!(manager.run(&name, origin, RunOptions { no_deps })?),
+        Request::Status { name } => json!(manager.status(&name)?),
+        Request::Stop {
+            name,
+            force,
+            timeout,
+            no_script,
+            tree,
+            cascade,
+        } => {
+            let options = StopOptions {
+                force,
+                timeout,
+                no_script,
+                tree,
+                cascade,
+                // the client doesn't see the stdio of this process
+                capture: true,
+            };
+            json!(manager.stop(&name, options)?)
+        }
+        Request::Kill { name, tree } => json!(manager.kill(&name, tree)?),
+        Request::Set { name, settings } => json!(manager.set(&name, &settings)?),
+        Request::History { name, limit } => json!(manager.history(&name, limit)?),
+        Request::Env { name, reveal } => json!(manager.env(&name, reveal)?),
+        Request::Delete { name, force } => json!(manager.delete(&name, force)?),
+        Request::Log {
+            name,
+            clear,
+            stdout,
+            stderr,
+            run,
+            since,
+            until,
+            merged,
+        } => {
+            // both streams unless one is asked for
+            let (stdout, stderr) = if stdout || stderr {
+                (stdout, stderr)
+            } else {
+                (true, true)
+            };
+            if clear {
+                json!(manager.clear_logs(&name, stdout, stderr)?)
+            } else {
+                let query = LogQuery {
+                    stdout,
+                    stderr,
+                    run,
+                    since,
+                    until,
+                    merged,
+                };
+                json!(manager.logs(&name, &query)?)
+            }
+        }
+        Request::Subscribe { .. } => unreachable!("handled by the connection"),
+    };
+    Ok(value)
+}
+
+/// Pushes `{"event": "changed", "app": ...}` and `{"event": "removed", "name": ...}`
+/// for every difference to `known`, the states of the apps the client was sent,
+/// until the connection is closed
+fn watch(
+    manager: &Manager,
+    writer: &Writer,
+    closed: &AtomicBool,
+    names: Vec<AppName>,
+    mut known: BTreeMap<String, Value>,
+) {
+    while !closed.load(Ordering::Relaxed) {
+        let Ok(apps) = manager.list() else {
+            thread::sleep(POLL_INTERVAL);
+            continue;
+        };
+        let mut seen = BTreeMap::new();
+        for app in apps {
+            if !names.is_empty() && !names.iter().any(|name| name.as_str() == app.name) {
+                continue;
+            }
+            let value = json!(app);
+            let state = state(&value);
+            if known.get(&app.name) != Some(&state) {
+                let event = json!({ "event": "changed", "app": value });
+                if !send(writer, &event) {
+                    return;
+                }
+            }
+            seen.insert(app.name, state);
+        }
+        for name in known.keys().filter(|name| !seen.contains_key(*name)) {
+            if !send(writer, &json!({ "event": "removed", "name": name })) {
+                return;
+            }
+        }
+        known = seen;
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// An app as `watch` compares it, uptime alone is not a change
+fn state(app: &Value) -> Value {
+    let mut state = app.clone();
+    state["uptime_secs"] = Value::Null;
+    state
+}
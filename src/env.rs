@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::config::AppConfig;
+use crate::error::Error;
+
+/// One variable of the environment an app is started with
+#[derive(Debug, Clone, Serialize)]
+pub struct EnvVar {
+    pub name: String,
+    pub value: String,
+    /// `inherited`, `app.toml` or the path of the .env file it came from
+    pub source: String,
+    /// looks like a password, token or key, masked by `execmgr env`
+    pub secret: bool,
+}
+
+/// Environment of the app: the variables of this process (only those in
+/// `pass_env` with `clean_env`), then the .env files in order, then `[env]`.
+/// Later sources override earlier ones. Names and values that are not UTF-8
+/// are shown lossily, `env_map` passes them on unchanged.
+pub fn resolve_env(app_dir: &Path, config: &AppConfig) -> Result<Vec<EnvVar>, Error> {
+    Ok(resolve(app_dir, config)?
+        .into_iter()
+        .map(|(name, (value, source))| {
+            let name = name.to_string_lossy().into_owned();
+            EnvVar {
+                secret: is_secret(&name),
+                name,
+                value: value.to_string_lossy().into_owned(),
+                source,
+            }
+        })
+        .collect())
+}
+
+/// The resolved environment as `name => value`, what the app actually gets
+pub fn env_map(app_dir: &Path, config: &AppConfig) -> Result<BTreeMap<OsString, OsString>, Error> {
+    Ok(resolve(app_dir, config)?
+        .into_iter()
+        .map(|(name, (value, _))| (name, value))
+        .collect())
+}
+
+/// `name => (value, source)`
+fn resolve(
+    app_dir: &Path,
+    config: &AppConfig,
+) -> Result<BTreeMap<OsString, (OsString, String)>, Error> {
+    let mut vars = BTreeMap::new();
+    // vars() would panic on a variable that isn't UTF-8
+    for (name, value) in std::env::vars_os() {
+        if !config.clean_env || config.pass_env.iter().any(|pass| name == pass.as_str()) {
+            vars.insert(name, (value, "inherited".into()));
+        }
+    }
+    for file in &config.env_files {
+        let path = app_dir.join(file);
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| Error::io(format!("unable to read env file {:?}", path), e))?;
+        for (name, value) in parse_env_file(&path, &text)? {
+            vars.insert(name.into(), (value.into(), path.display().to_string()));
+        }
+    }
+    for (name, value) in &config.env {
+        vars.insert(name.into(), (value.into(), "app.toml".into()));
+    }
+    Ok(vars)
+}
+
+/// Parses `KEY=value` lines. Blank lines and `#` comments are skipped, an
+/// `export ` prefix is allowed, values may be quoted: `'...'` is taken as is,
+/// `"..."` understands `\n`, `\t`, `\"` and `\\`.
+pub fn parse_env_file(path: &Path, text: &str) -> Result<Vec<(String, String)>, Error> {
+    let mut vars = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let error = |message: String| Error::Config {
+            path: path.to_path_buf(),
+            line: Some(index + 1),
+            column: None,
+            message,
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((name, value)) = line.split_once('=') else {
+            return Err(error(format!("expected KEY=value, found {:?}", line)));
+        };
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(error(format!("invalid variable name {:?}", name)));
+        }
+        let value = value.trim();
+        let value = if let Some(quoted) = value.strip_prefix('\'') {
+            quoted
+                .strip_suffix('\'')
+                .ok_or_else(|| error("unterminated single quote".into()))?
+                .to_string()
+        } else if let Some(quoted) = value.strip_prefix('"') {
+            let quoted = quoted
+                .strip_suffix('"')
+                .ok_or_else(|| error("unterminated double quote".into()))?;
+            unescape(quoted)
+        } else {
+            // unquoted values end at a comment
+            match value.find(" #") {
+                Some(i) => value[..i].trim_end().to_string(),
+                None => value.to_string(),
+            }
+        };
+        vars.push((name.to_string(), value));
+    }
+    Ok(vars)
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Whether the variable probably holds a credential
+pub fn is_secret(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    [
+        "SECRET",
+        "PASSWORD",
+        "PASSWD",
+        "TOKEN",
+        "CREDENTIAL",
+        "PRIVATE",
+        "API_KEY",
+        "ACCESS_KEY",
+        "AUTH",
+    ]
+    .iter()
+    .any(|word| name.contains(word))
+        || name == "KEY"
+        || name.ends_with("_KEY")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Vec<(String, String)>, Error> {
+        parse_env_file(Path::new(".env"), text)
+    }
+
+    fn pair(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn parse_env_file_values() {
+        let text = [
+            "# comment",
+            "PLAIN=value",
+            "",
+            "export EXPORTED=1",
+            "  SPACED = padded  ",
+            "EMPTY=",
+            "EQUALS=a=b",
+            "COMMENTED=value # comment",
+            "HASH=a#b",
+            r"SINGLE='raw \n # kept'",
+            r#"DOUBLE="line\nnext\t\"quoted\" \\""#,
+        ]
+        .join("\n");
+        assert_eq!(
+            parse(&text).unwrap(),
+            vec![
+                pair("PLAIN", "value"),
+                pair("EXPORTED", "1"),
+                pair("SPACED", "padded"),
+                pair("EMPTY", ""),
+                pair("EQUALS", "a=b"),
+                pair("COMMENTED", "value"),
+                pair("HASH", "a#b"),
+                pair("SINGLE", "raw \\n # kept"),
+                pair("DOUBLE", "line\nnext\t\"quoted\" \\"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_env_file_errors_point_at_the_line() {
+        for (text, expected_line) in [
+            ("A=1\nno equals sign", 2),
+            ("=value", 1),
+            ("A=1\n\nMY VAR=1", 3),
+            ("A='open", 1),
+            ("# comment\nA=\"open", 2),
+        ] {
+            match parse(text) {
+                Err(Error::Config { line, .. }) => {
+                    assert_eq!(line, Some(expected_line), "{:?}", text)
+                }
+                other => panic!("{:?} parsed as {:?}", text, other),
+            }
+        }
+    }
+}
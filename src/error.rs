@@ -0,0 +1,147 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Errors of every execmgr command. Each kind exits with its own code so
+/// scripts can tell e.g. "already running" apart from a real failure.
+#[derive(Debug)]
+pub enum Error {
+    AppNotFound(String),
+    AppExists(String),
+    AlreadyRunning {
+        name: String,
+        pid: Option<u32>,
+    },
+    /// the app is down but its supervisor is about to restart it
+    RestartPending {
+        name: String,
+        next_retry: Option<String>,
+    },
+    NotRunning(String),
+    /// app.json or history.jsonl could not be parsed
+    CorruptMetadata {
+        path: PathBuf,
+        reason: String,
+    },
+    Io {
+        context: String,
+        source: io::Error,
+    },
+    /// the recorded pid no longer belongs to the app
+    PidMismatch(String),
+    /// invalid input or settings
+    Invalid(String),
+    /// a script, the supervisor or a signal failed
+    Failed(String),
+    /// app.toml or a .env file could not be parsed, or has an invalid setting
+    Config {
+        path: PathBuf,
+        line: Option<usize>,
+        column: Option<usize>,
+        message: String,
+    },
+    /// the app died or timed out before its readiness probe passed
+    NotReady {
+        name: String,
+        reason: String,
+        /// last lines of stderr.log
+        stderr: String,
+    },
+    /// reported by the daemon or a supervisor, with the exit code it maps to
+    Remote {
+        message: String,
+        code: i32,
+    },
+}
+
+impl Error {
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        Error::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    /// Process exit code, 2 matches clap's usage errors
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Failed(_) => 1,
+            Error::Invalid(_) | Error::Config { .. } => 2,
+            Error::AppNotFound(_) => 3,
+            Error::AppExists(_) => 4,
+            Error::AlreadyRunning { .. } => 5,
+            Error::RestartPending { .. } => 6,
+            Error::NotRunning(_) => 7,
+            Error::CorruptMetadata { .. } => 8,
+            Error::Io { .. } => 9,
+            Error::PidMismatch(_) => 10,
+            Error::NotReady { .. } => 11,
+            Error::Remote { code, .. } => *code,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::AppNotFound(name) => write!(f, "app '{}' not exists.", name),
+            Error::AppExists(name) => write!(f, "app '{}' already exists.", name),
+            Error::AlreadyRunning {
+                name,
+                pid: Some(pid),
+            } => write!(f, "app '{}' is already running (pid: {})", name, pid),
+            Error::AlreadyRunning { name, pid: None } => {
+                write!(f, "app '{}' is already running", name)
+            }
+            Error::RestartPending { name, next_retry } => write!(
+                f,
+                "app '{}' is waiting to restart (next retry: {}), stop it first",
+                name,
+                next_retry.as_deref().unwrap_or("-")
+            ),
+            Error::NotRunning(name) => write!(f, "app '{}' is not running", name),
+            Error::CorruptMetadata { path, reason } => {
+                write!(f, "data is corrupted in {:?}: {}", path, reason)
+            }
+            Error::Io { context, source } => write!(f, "{}: {}", context, source),
+            Error::Config {
+                path,
+                line: Some(line),
+                column,
+                message,
+            } => write!(
+                f,
+                "{}:{}:{}: {}",
+                path.display(),
+                line,
+                column.unwrap_or(1),
+                message
+            ),
+            Error::Config { path, message, .. } => write!(f, "{}: {}", path.display(), message),
+            Error::NotReady {
+                name,
+                reason,
+                stderr,
+            } => {
+                write!(f, "app '{}' is not ready: {}", name, reason)?;
+                if !stderr.is_empty() {
+                    write!(f, "\n--- stderr ---\n{}", stderr)?;
+                }
+                Ok(())
+            }
+            Error::PidMismatch(message)
+            | Error::Invalid(message)
+            | Error::Failed(message)
+            | Error::Remote { message, .. } => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+
+use crate::app::{Health, HealthProbe, StopSettings, split_host_port};
+use crate::config::{AppConfig, read_config};
+use crate::env::env_map;
+use crate::utils::{parse_signal, signal_app, update_app, wait_stopped};
+
+/// Probes the app every `interval_secs` while it runs and records the result
+/// in app.json. app.toml is read again before every probe, so changes apply
+/// to a running app. Returns true when it stopped the app because it
+/// became unhealthy and `restart` is set.
+pub fn monitor(app_dir: PathBuf, pid: u32, done: Arc<AtomicBool>) -> JoinHandle<bool> {
+    thread::spawn(move || {
+        loop {
+            let Ok(config) = read_config(&app_dir) else {
+                return false;
+            };
+            if !sleep_unless_done(&done, Duration::from_secs(config.health.interval_secs)) {
+                return false;
+            }
+            let Ok(config) = read_config(&app_dir) else {
+                return false;
+            };
+            let Some(probe) = &config.health.probe else {
+                continue;
+            };
+            let timeout = Duration::from_secs(config.health.timeout_secs);
+            let result = check(probe, config.health.status, timeout, &app_dir, &config);
+            if done.load(Ordering::Relaxed) {
+                return false;
+            }
+
+            let update = update_app(&app_dir, |app| {
+                let state = &mut app.health_state;
+                let was_unhealthy = state.status == Health::Unhealthy;
+                state.checked_at = Some(Local::now().to_rfc3339());
+                match result {
+                    Ok(()) => {
+                        state.status = Health::Healthy;
+                        state.failures = 0;
+                        state.last_error = None;
+                    }
+                    Err(error) => {
+                        state.failures += 1;
+                        state.last_error = Some(error);
+                        if state.failures >= config.health.threshold {
+                            state.status = Health::Unhealthy;
+                        }
+                    }
+                }
+                Ok(!was_unhealthy && state.status == Health::Unhealthy)
+            });
+            let Ok(unhealthy) = update else {
+                return false;
+            };
+            if unhealthy && config.health.restart {
+                stop_app(&app_dir, pid, &config.stop);
+                return true;
+            }
+        }
+    })
+}
+
+/// Sleeps for `duration`, returns false as soon as `done` is set
+fn sleep_unless_done(done: &AtomicBool, duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        if done.load(Ordering::Relaxed) {
+            return false;
+        }
+        thread::sleep(left.min(Duration::from_millis(200)));
+    }
+    !done.load(Ordering::Relaxed)
+}
+
+/// Stop signal, then SIGKILL after the stop timeout, like `stop --no-script`
+fn stop_app(app_dir: &Path, pid: u32, stop: &StopSettings) {
+    let signal = parse_signal(&stop.signal).unwrap_or(libc::SIGTERM);
+    if signal_app(app_dir, pid, signal, stop.descendants).is_err() {
+        return;
+    }
+    if !wait_stopped(app_dir, Duration::from_secs(stop.timeout_secs)).unwrap_or(false) {
+        signal_app(app_dir, pid, libc::SIGKILL, stop.descendants).ok();
+    }
+}
+
+/// Runs one probe, `status` is what an http probe expects. A command runs
+/// with the working dir and environment of the app. The error says why it failed.
+pub fn check(
+    probe: &HealthProbe,
+    status: u16,
+    timeout: Duration,
+    app_dir: &Path,
+    config: &AppConfig,
+) -> Result<(), String> {
+    match probe {
+        HealthProbe::Command(command) => check_command(command, app_dir, config, timeout),
+        HealthProbe::Tcp { host, port } => connect(host, *port, timeout).map(drop),
+        HealthProbe::Http(url) => check_http(url, status, timeout),
+    }
+}
+
+fn check_command(
+    command: &str,
+    app_dir: &Path,
+    config: &AppConfig,
+    timeout: Duration,
+) -> Result<(), String> {
+    let env = env_map(app_dir, config).map_err(|e| e.to_string())?;
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(config.work_dir(app_dir))
+        .env_clear()
+        .envs(&env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()
+        .map_err(|e| format!("failed to run {:?}: {}", command, e))?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return Ok(()),
+            Ok(Some(status)) => return Err(format!("command failed ({})", status)),
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+            Ok(None) => {
+                // SAFETY: kill(2) only takes plain integers, a negative pid targets the group
+                unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+                child.wait().ok();
+                return Err(format!("command timed out after {}s", timeout.as_secs()));
+            }
+            Err(e) => return Err(format!("failed to wait for command: {}", e)),
+        }
+    }
+}
+
+fn connect(host: &str, port: u16, timeout: Duration) -> Result<TcpStream, String> {
+    let addresses: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("failed to resolve {}: {}", host, e))?
+        .collect();
+    let mut error = format!("no address for {}", host);
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => error = format!("failed to connect to {}: {}", address, e),
+        }
+    }
+    Err(error)
+}
+
+fn check_http(url: &str, expected: u16, timeout: Duration) -> Result<(), String> {
+    let rest = url.strip_prefix("http://").unwrap_or(url);
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let (host, port) = match split_host_port(authority) {
+        Some((host, Some(port))) => (
+            host,
+            port.parse()
+                .map_err(|_| format!("invalid port in {:?}", url))?,
+        ),
+        Some((host, None)) => (host, 80),
+        None => return Err(format!("invalid host in {:?}", url)),
+    };
+
+    let mut stream = connect(host, port, timeout)?;
+    stream.set_read_timeout(Some(timeout)).ok();
+    stream.set_write_timeout(Some(timeout)).ok();
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, authority
+    )
+    .map_err(|e| format!("failed to send request: {}", e))?;
+
+    // only the status line is needed
+    let mut response = Vec::new();
+    let mut buffer = [0; 512];
+    while !response.contains(&b'\n') && response.len() < 4096 {
+        match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => response.extend_from_slice(&buffer[..n]),
+            Err(e) => return Err(format!("failed to read response: {}", e)),
+        }
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status = response
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| "invalid http response".to_string())?;
+    if status == expected {
+        Ok(())
+    } else {
+        Err(format!("http status {} (expected {})", status, expected))
+    }
+}
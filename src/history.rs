@@ -0,0 +1,40 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use crate::app::RunRecord;
+use crate::error::Error;
+
+/// Appends a finished run to the app's `history.jsonl`
+pub fn append_history(app_dir: &Path, record: &RunRecord) -> Result<(), Error> {
+    let history_path = app_dir.join("history.jsonl");
+    let line = serde_json::to_string(record)
+        .map_err(|e| Error::Failed(format!("failed to dump run record: {}", e)))?;
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&history_path)
+        .map_err(|e| Error::io(format!("failed to open {:?}", history_path), e))?;
+    writeln!(f, "{}", line).map_err(|e| Error::io(format!("failed to write {:?}", history_path), e))
+}
+
+/// Reads every recorded run, oldest first
+pub fn read_history(app_dir: &Path) -> Result<Vec<RunRecord>, Error> {
+    let history_path = app_dir.join("history.jsonl");
+    if !history_path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = std::fs::read_to_string(&history_path)
+        .map_err(|e| Error::io(format!("unable to read {:?}", history_path), e))?;
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).map_err(|e| Error::CorruptMetadata {
+                path: history_path.clone(),
+                reason: format!("line {}: {}", i + 1, e),
+            })
+        })
+        .collect()
+}
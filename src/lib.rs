@@ -0,0 +1,38 @@
+//! execmgr as a library: the `execmgr` binary is a thin CLI over [`Manager`],
+//! other Rust tools can embed it the same way.
+//!
+//! ```no_run
+//! use execmgr::{Manager, Origin, RunOptions};
+//!
+//! let manager = Manager::from_env().with_executable("/usr/local/bin/execmgr");
+//! for status in manager.list()? {
+//!     println!("{} running: {}", status.name, status.running);
+//! }
+//! let info = manager.run(&"web".parse()?, Origin::Api, RunOptions::default())?;
+//! println!("started with pid {}", info.pid);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod app;
+pub mod config;
+mod env;
+pub mod error;
+mod health;
+mod history;
+mod logs;
+mod manager;
+mod procfs;
+mod supervisor;
+#[cfg(test)]
+mod testutil;
+pub mod utils;
+
+pub use app::{App, AppName, Health, Origin, RunRecord};
+pub use config::AppConfig;
+pub use env::EnvVar;
+pub use error::Error;
+pub use manager::{
+    AppStatus, ClearResult, DeleteResult, Info, KillResult, Killed, LogLine, LogQuery, Manager,
+    RunInfo, RunOptions, ScriptOutput, Scripts, Settings, StopEvent, StopOptions, StopResult,
+    Stopped, Stream,
+};
@@ -0,0 +1,307 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+
+use chrono::{DateTime, Local, SecondsFormat};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+use crate::app::LogSettings;
+use crate::error::Error;
+
+/// Timestamp written in front of every captured line when timestamps are enabled
+pub fn timestamp_now() -> String {
+    Local::now().to_rfc3339_opts(SecondsFormat::Micros, false)
+}
+
+/// Timestamp at the start of a captured line
+pub fn line_time(line: &str) -> Option<DateTime<Local>> {
+    let (stamp, _) = line.split_once(' ')?;
+    DateTime::parse_from_rfc3339(stamp)
+        .ok()
+        .map(|t| t.with_timezone(&Local))
+}
+
+/// Text of a captured line without its timestamp
+pub fn strip_timestamp(line: &str) -> &str {
+    match line.split_once(' ') {
+        Some((stamp, text)) if DateTime::parse_from_rfc3339(stamp).is_ok() => text,
+        _ => line,
+    }
+}
+
+/// Keeps the lines logged between `since` and `until`.
+/// A line without a timestamp belongs to the timestamped line above it.
+pub fn filter_lines(
+    content: &str,
+    since: Option<DateTime<Local>>,
+    until: Option<DateTime<Local>>,
+) -> Vec<&str> {
+    let mut keep = since.is_none();
+    content
+        .lines()
+        .filter(|line| {
+            if let Some(time) = line_time(line) {
+                keep = since.is_none_or(|since| time >= since)
+                    && until.is_none_or(|until| time <= until);
+            }
+            keep
+        })
+        .collect()
+}
+
+/// Path of rotated segment `index` (1 = newest), e.g. `stdout.log.1.gz`
+pub fn segment_path(log: &Path, index: u32, compressed: bool) -> PathBuf {
+    let mut name = log.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    if compressed {
+        name.push(".gz");
+    }
+    PathBuf::from(name)
+}
+
+/// Rotated segments of a log file, oldest first
+pub fn rotated_segments(log: &Path) -> Vec<PathBuf> {
+    let mut segments = Vec::new();
+    let mut index = 1;
+    loop {
+        let plain = segment_path(log, index, false);
+        let compressed = segment_path(log, index, true);
+        if compressed.exists() {
+            segments.push(compressed);
+        } else if plain.exists() {
+            segments.push(plain);
+        } else {
+            break;
+        }
+        index += 1;
+    }
+    segments.reverse();
+    segments
+}
+
+/// Reads a log file including all of its rotated segments
+pub fn read_log(log: &Path) -> Result<String, Error> {
+    let mut content = read_segments(log)?.into_bytes();
+    if log.exists() {
+        File::open(log)
+            .and_then(|mut f| f.read_to_end(&mut content))
+            .map_err(|e| Error::io(format!("failed to read {:?}", log), e))?;
+    }
+    Ok(String::from_utf8_lossy(&content).into_owned())
+}
+
+/// Reads the rotated segments of a log file, without the current one
+pub fn read_segments(log: &Path) -> Result<String, Error> {
+    let mut content = Vec::new();
+    for segment in rotated_segments(log) {
+        let file = File::open(&segment)
+            .map_err(|e| Error::io(format!("failed to open {:?}", segment), e))?;
+        let result = if segment.extension().is_some_and(|ext| ext == "gz") {
+            GzDecoder::new(file).read_to_end(&mut content)
+        } else {
+            BufReader::new(file).read_to_end(&mut content)
+        };
+        result.map_err(|e| Error::io(format!("failed to read {:?}", segment), e))?;
+    }
+    Ok(String::from_utf8_lossy(&content).into_owned())
+}
+
+/// Shifts `log` into `log.1[.gz]`, keeping at most `max_files` rotated segments
+pub fn rotate_log(log: &Path, max_files: u32, compress: bool) -> io::Result<()> {
+    for index in (1..=max_files.max(1)).rev() {
+        for compressed in [false, true] {
+            let from = segment_path(log, index, compressed);
+            if !from.exists() {
+                continue;
+            }
+            if index >= max_files {
+                std::fs::remove_file(&from)?;
+            } else {
+                std::fs::rename(&from, segment_path(log, index + 1, compressed))?;
+            }
+        }
+    }
+    if max_files == 0 {
+        return std::fs::remove_file(log);
+    }
+
+    let rotated = segment_path(log, 1, false);
+    std::fs::rename(log, &rotated)?;
+    if compress {
+        let mut encoder = GzEncoder::new(
+            File::create(segment_path(log, 1, true))?,
+            Compression::default(),
+        );
+        io::copy(&mut File::open(&rotated)?, &mut encoder)?;
+        encoder.finish()?;
+        std::fs::remove_file(&rotated)?;
+    }
+    Ok(())
+}
+
+/// Removes every rotated segment of a log file
+pub fn remove_segments(log: &Path) -> Result<(), Error> {
+    for segment in rotated_segments(log) {
+        std::fs::remove_file(&segment)
+            .map_err(|e| Error::io(format!("failed to remove {:?}", segment), e))?;
+    }
+    Ok(())
+}
+
+struct LogWriter {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    at_line_start: bool,
+    settings: LogSettings,
+}
+
+impl LogWriter {
+    fn create(path: PathBuf, settings: LogSettings) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)?;
+        Ok(LogWriter {
+            path,
+            file,
+            written: 0,
+            at_line_start: true,
+            settings,
+        })
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let max_size = self.settings.max_size;
+        if max_size > 0 && self.written > 0 && self.written + bytes.len() as u64 > max_size {
+            // on failure keep appending to the current file rather than dropping output
+            self.rotate().ok();
+        }
+        if self.settings.timestamps && self.at_line_start {
+            let stamp = timestamp_now();
+            write!(self.file, "{} ", stamp)?;
+            self.written += stamp.len() as u64 + 1;
+        }
+        self.file.write_all(bytes)?;
+        self.written += bytes.len() as u64;
+        self.at_line_start = bytes.ends_with(b"\n");
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        rotate_log(&self.path, self.settings.max_files, self.settings.compress)?;
+        let at_line_start = self.at_line_start;
+        *self = LogWriter::create(self.path.clone(), self.settings.clone())?;
+        self.at_line_start = at_line_start;
+        Ok(())
+    }
+}
+
+/// Copies the output of the app into `path` on a background thread,
+/// rotating the file whenever it grows past `settings.max_size` and
+/// prefixing every line with its timestamp when `settings.timestamps` is set.
+pub fn capture_log(
+    source: impl Read + Send + 'static,
+    path: PathBuf,
+    settings: LogSettings,
+) -> Result<JoinHandle<()>, Error> {
+    let mut writer = LogWriter::create(path.clone(), settings)
+        .map_err(|e| Error::io(format!("failed to open log {:?}", path), e))?;
+    Ok(thread::spawn(move || {
+        let mut reader = BufReader::new(source);
+        loop {
+            let chunk = match reader.fill_buf() {
+                Ok([]) | Err(_) => break,
+                Ok(chunk) => chunk,
+            };
+            // write whole lines where possible so rotation does not split them
+            let len = chunk
+                .iter()
+                .position(|b| *b == b'\n')
+                .map(|i| i + 1)
+                .unwrap_or(chunk.len());
+            // keep draining the pipe even if the disk is full, the app must not block
+            writer.write(&chunk[..len]).ok();
+            reader.consume(len);
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::temp_dir;
+
+    /// `stdout.log` in a fresh dir for one test
+    fn log_path(test: &str) -> PathBuf {
+        temp_dir(test).join("stdout.log")
+    }
+
+    fn read(path: &Path) -> String {
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn rotate_log_shifts_and_drops_segments() {
+        let log = log_path("rotate");
+        for content in ["one\n", "two\n", "three\n"] {
+            std::fs::write(&log, content).unwrap();
+            rotate_log(&log, 2, false).unwrap();
+        }
+        assert!(!log.exists());
+        assert_eq!(read(&segment_path(&log, 1, false)), "three\n");
+        assert_eq!(read(&segment_path(&log, 2, false)), "two\n");
+        assert!(!segment_path(&log, 3, false).exists());
+        assert_eq!(read_segments(&log).unwrap(), "two\nthree\n");
+        std::fs::remove_dir_all(log.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn rotate_log_compresses_segments() {
+        let log = log_path("rotate-gz");
+        for content in ["one\n", "two\n"] {
+            std::fs::write(&log, content).unwrap();
+            rotate_log(&log, 3, true).unwrap();
+        }
+        assert!(!segment_path(&log, 1, false).exists());
+        assert!(segment_path(&log, 1, true).exists());
+        assert!(segment_path(&log, 2, true).exists());
+        assert_eq!(read_segments(&log).unwrap(), "one\ntwo\n");
+        std::fs::remove_dir_all(log.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn rotate_log_keeps_older_plain_segments_when_compression_is_turned_on() {
+        let log = log_path("rotate-mixed");
+        std::fs::write(&log, "plain\n").unwrap();
+        rotate_log(&log, 3, false).unwrap();
+        std::fs::write(&log, "compressed\n").unwrap();
+        rotate_log(&log, 3, true).unwrap();
+        assert_eq!(read(&segment_path(&log, 2, false)), "plain\n");
+        assert_eq!(read_segments(&log).unwrap(), "plain\ncompressed\n");
+        std::fs::remove_dir_all(log.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn rotate_log_without_segments_removes_the_log() {
+        let log = log_path("rotate-none");
+        std::fs::write(&log, "gone\n").unwrap();
+        rotate_log(&log, 0, false).unwrap();
+        assert!(!log.exists());
+        assert!(rotated_segments(&log).is_empty());
+        std::fs::remove_dir_all(log.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn strip_timestamp_only_strips_timestamps() {
+        assert_eq!(
+            strip_timestamp("2024-03-01T12:30:05.123456+01:00 Listening on :80"),
+            "Listening on :80"
+        );
+        assert_eq!(strip_timestamp("Listening on :80"), "Listening on :80");
+    }
+}
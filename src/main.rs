@@ -1,150 +1,149 @@
-mod app;
 mod cli;
-mod utils;
+mod control;
+mod server;
+#[cfg(test)]
+mod testutil;
 
-use std::fs::{OpenOptions, create_dir_all, read_dir, remove_dir_all};
 use std::io;
 use std::io::Write;
-use std::os::unix::fs::PermissionsExt;
-use std::path::PathBuf;
+use std::path::Path;
 
-use chrono::Local;
 use clap::Parser;
-
-use crate::app::{App, LastRunInfo};
-use crate::cli::Commands;
-use crate::utils::{
-    check_running, kill_pid, log_paths, resolve_base_dir, run_attached, since_running,
-    spawn_detached,
+use serde::Serialize;
+use serde_json::json;
+
+use crate::cli::{Commands, Output};
+use execmgr::app::{HealthProbe, RestartMode, signal_name};
+use execmgr::config::CONFIG_FILE;
+use execmgr::utils::{format_size, shell_join};
+use execmgr::{
+    AppName, AppStatus, Error, Killed, LogLine, LogQuery, Manager, Origin, RunInfo, RunOptions,
+    Settings, StopEvent, StopOptions, Stopped,
 };
 
-fn create_app(basedir: &PathBuf, name: &str) -> Result<(), String> {
-    let path = basedir.join(name);
-    if path.exists() {
-        return Err(format!("app '{}' already exists.", name));
-    }
-    create_dir_all(&path).map_err(|_| format!("failed to create path: {:?}", path))?;
-    let files = ["start.sh", "stop.sh"];
-    for file in files {
-        let filepath = path.join(file);
-        let mut f = OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .open(&filepath)
-            .map_err(|_| format!("failed to create file: {:?}", filepath))?;
-        writeln!(
-            f,
-            "#!/bin/sh\nset -e\nexec echo 'hello from {}'",
-            name.replace("'", "`")
-        )
-        .map_err(|_| format!("failed to create file: {:?}", filepath))?;
-
-        // chmod +x (755)
-        let mut perms = f
-            .metadata()
-            .map_err(|_| format!("failed to get metadata: {:?}", filepath))?
-            .permissions();
-
-        perms.set_mode(0o755);
-
-        std::fs::set_permissions(&filepath, perms)
-            .map_err(|_| format!("failed to set permissions: {:?}", filepath))?;
-    }
-    let app = App {
-        name: name.to_string(),
-        created_at: Local::now().to_rfc3339(),
-        last_run: None,
-        num_runs: 0,
+/// Prints `value` as pretty JSON, the `--output json` form of every command
+fn print_json(value: &impl Serialize) -> Result<(), Error> {
+    let json = serde_json::to_string_pretty(value)
+        .map_err(|e| Error::Failed(format!("failed to dump json: {}", e)))?;
+    println!("{}", json);
+    Ok(())
+}
+
+/// One row of `--output plain`: tab-separated, no padding or truncation
+fn print_plain(fields: &[String]) {
+    println!("{}", fields.join("\t"));
+}
+
+fn create_app(
+    manager: &Manager,
+    name: &AppName,
+    command: Vec<String>,
+    output: Output,
+) -> Result<(), Error> {
+    let status = if command.is_empty() {
+        manager.create(name)?
+    } else {
+        manager.create_command(name, command)?
     };
-    let app_json =
-        serde_json::to_string_pretty(&app).expect("Something went wrong while dumping app json.");
-    let filepath = path.join("app.json");
-    let mut f = OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(&filepath)
-        .map_err(|_| format!("failed to create file: {:?}", filepath))?;
-    write!(f, "{}", app_json).map_err(|_| format!("failed to create file: {:?}", filepath))?;
+    if output == Output::Json {
+        return print_json(&status);
+    }
 
     println!("created app '{}'", name);
-    println!("path: {:?}", path);
-    println!("start script: start.sh");
+    println!("path: {:?}", status.path);
+    match &status.config.command {
+        Some(command) => println!("command: {}", shell_join(command)),
+        None => println!("start script: start.sh"),
+    }
 
     Ok(())
 }
 
-fn run_app(basedir: &PathBuf, name: &str) -> Result<(), String> {
-    let path = basedir.join(name);
-    if !path.exists() {
-        return Err(format!("app '{}' not exists.", name));
+fn run_app(
+    manager: &Manager,
+    name: &AppName,
+    wait_ready: Option<Option<u64>>,
+    no_deps: bool,
+    output: Output,
+) -> Result<(), Error> {
+    if wait_ready.is_some() && manager.config(name)?.ready.probe.is_none() {
+        return Err(Error::Invalid(format!(
+            "no readiness probe set for '{}', see `set --ready`",
+            name
+        )));
     }
-    let script = path.join("start.sh");
-    if !script.exists() {
-        return Err(format!("'{:?}'  not found for '{}'.", script, name));
+    let mut info = start_app(manager, name, no_deps)?;
+    if let Some(timeout) = wait_ready {
+        info.ready_secs = Some(manager.wait_ready(name, timeout)?.as_secs_f64());
     }
-
-    let json_path = path.join("app.json");
-    let mut app: App = serde_json::from_str(
-        &std::fs::read_to_string(&json_path)
-            .map_err(|_| format!("unable to read {:?}", json_path))?,
-    )
-    .map_err(|_| format!("data is corrupted in {:?}", json_path))?;
-
-    if check_running(&path) {
-        return Err(format!(
-            "app '{}' is already running (pid: {})",
-            name,
-            app.last_run
-                .map(|last_run| last_run.pid.to_string())
-                .unwrap_or("None".into())
-        ));
+    if output == Output::Json {
+        return print_json(&info);
+    }
+    for dependency in &info.dependencies {
+        println!("started dependency '{}' (pid {})", dependency.name, dependency.pid);
+        if let Some(ready) = dependency.ready_secs {
+            println!("'{}' ready after {:.1}s", dependency.name, ready);
+        }
     }
-
-    let child = spawn_detached(&script, &path)?;
-    let last_run = LastRunInfo {
-        time: Local::now().to_rfc3339(),
-        pid: child,
-    };
     println!("started '{}'", name);
-    println!("pid: {}", last_run.pid);
+    println!("pid: {}", info.pid);
     println!(
         "started at: {}",
-        last_run
-            .time
+        info.started_at
             .get(..19)
-            .unwrap_or(&last_run.time)
+            .unwrap_or(&info.started_at)
             .replace('T', " ")
     );
-    app.last_run = Some(last_run);
-    app.num_runs = app.num_runs + 1;
-
-    std::fs::write(
-        &json_path,
-        serde_json::to_string_pretty(&app)
-            .map_err(|_| "Something went wrong while dumping app json.")?,
-    )
-    .map_err(|_| format!("Something went wrong while writing to {:?}.", json_path))?;
+    if let Some(ready) = info.ready_secs {
+        println!("ready after: {:.1}s", ready);
+    }
 
     Ok(())
 }
-fn status_app(basedir: &PathBuf, name: &str) -> Result<(), String> {
-    let path = basedir.join(name);
-
-    if !path.exists() {
-        return Err(format!("app '{}' not exists.", name));
+/// Starts the app and its dependencies: a running daemon becomes their parent,
+/// without one we start our own supervisors
+fn start_app(manager: &Manager, name: &AppName, no_deps: bool) -> Result<RunInfo, Error> {
+    let socket = control::socket_path(manager);
+    // a plain `listen` would only spawn the same supervisor we do
+    let daemon = control::request(&socket, &json!({ "command": "info" }))
+        .and_then(Result::ok)
+        .is_some_and(|info| info["daemon"] == true);
+    if !daemon {
+        return manager.run(name, Origin::Cli, RunOptions { no_deps });
+    }
+    let request =
+        json!({ "command": "run", "name": name, "origin": Origin::Cli, "no_deps": no_deps });
+    match control::request(&socket, &request) {
+        Some(result) => serde_json::from_value(result?)
+            .map_err(|e| Error::Failed(format!("invalid response from daemon: {}", e))),
+        None => manager.run(name, Origin::Cli, RunOptions { no_deps }),
     }
+}
 
-    let json_path = path.join("app.json");
-    let app: App = serde_json::from_str(
-        &std::fs::read_to_string(&json_path)
-            .map_err(|e| format!("unable to read {:?}: {}", json_path, e))?,
-    )
-    .map_err(|e| format!("data is corrupted in {:?}: {}", json_path, e))?;
-
-    let running = check_running(&path);
+fn status_app(manager: &Manager, name: &AppName, output: Output) -> Result<(), Error> {
+    let status = manager.status(name)?;
+    if output == Output::Json {
+        return print_json(&status);
+    }
+    let app = &status.app;
+    let config = &status.config;
+    let path = &status.path;
 
     println!("name        : {}", app.name);
     println!("path        : {}", path.display());
+    println!("config      : {}", path.join(CONFIG_FILE).display());
+    match &config.command {
+        Some(command) => println!("command     : {}", shell_join(command)),
+        None => println!("command     : start.sh"),
+    }
+    println!("cwd         : {}", config.work_dir(path).display());
+    if !config.tags.is_empty() {
+        println!("tags        : {}", config.tags.join(", "));
+    }
+    if !config.depends_on.is_empty() {
+        let names: Vec<&str> = config.depends_on.iter().map(AppName::as_str).collect();
+        println!("depends on  : {}", names.join(", "));
+    }
     println!(
         "created     : {}",
         app.created_at
@@ -161,27 +160,95 @@ fn status_app(basedir: &PathBuf, name: &str) -> Result<(), String> {
                 last.time.get(..19).unwrap_or(&last.time).replace('T', " ")
             );
             println!("last pid    : {}", last.pid);
+            println!(
+                "ended       : {}",
+                last.ended_at
+                    .as_ref()
+                    .map(|t| t.get(..19).unwrap_or(t).replace('T', " "))
+                    .unwrap_or_else(|| "-".into())
+            );
+            println!(
+                "exit        : {}",
+                match (last.exit_code, last.signal) {
+                    (Some(code), _) => format!("code {}", code),
+                    (None, Some(signal)) => format!("killed by {}", signal_name(signal)),
+                    (None, None) => "-".into(),
+                }
+            );
         }
         None => {
             println!("last run    : -");
             println!("last pid    : -");
+            println!("ended       : -");
+            println!("exit        : -");
         }
     }
 
-    println!("running     : {}", if running { "yes" } else { "no" });
+    println!("running     : {}", if status.running { "yes" } else { "no" });
     println!(
         "uptime      : {}",
-        if running {
-            app.last_run
-                .as_ref()
-                .map(|l| {
-                    since_running(&l.time)
-                        .map(|value| value.to_string())
-                        .unwrap_or_else(|| "-".into())
-                })
-                .unwrap_or_else(|| "-".into())
+        status
+            .uptime_secs
+            .map(|value| value.to_string())
+            .unwrap_or_else(|| "-".into())
+    );
+
+    println!(
+        "stop        : {}{} then SIGKILL after {}s",
+        if config.stop.use_script && path.join("stop.sh").exists() {
+            "stop.sh, "
         } else {
-            "-".into()
+            ""
+        },
+        config.stop.signal,
+        config.stop.timeout_secs
+    );
+    match config.restart.mode {
+        RestartMode::Never => println!("restart     : {}", config.restart.mode),
+        mode => println!(
+            "restart     : {} (max retries: {}, backoff: {}s..{}s, reset after: {}s)",
+            mode,
+            if config.restart.max_retries == 0 {
+                "unlimited".to_string()
+            } else {
+                config.restart.max_retries.to_string()
+            },
+            config.restart.backoff_secs,
+            config.restart.max_backoff_secs,
+            config.restart.reset_after_secs
+        ),
+    }
+    match &config.ready.probe {
+        Some(probe) => println!("ready       : {} (timeout {}s)", probe, config.ready.timeout_secs),
+        None => println!("ready       : -"),
+    }
+    match &config.health.probe {
+        Some(probe) => {
+            println!(
+                "health check: {} every {}s (timeout {}s, unhealthy after {} failures{})",
+                probe,
+                config.health.interval_secs,
+                config.health.timeout_secs,
+                config.health.threshold,
+                if config.health.restart { ", restart" } else { "" }
+            );
+            let state = &app.health_state;
+            match (status.health, &state.last_error) {
+                (Some(health), Some(error)) if state.failures > 0 => {
+                    println!("health      : {} ({} failed: {})", health, state.failures, error)
+                }
+                (Some(health), _) => println!("health      : {}", health),
+                (None, _) => println!("health      : -"),
+            }
+        }
+        None => println!("health check: -"),
+    }
+    println!("restarts    : {}", app.restarts.count);
+    println!(
+        "next retry  : {}",
+        match &app.restarts.next_retry_at {
+            Some(t) if status.pending_restart => t.get(..19).unwrap_or(t).replace('T', " "),
+            _ => "-".into(),
         }
     );
 
@@ -190,542 +257,640 @@ fn status_app(basedir: &PathBuf, name: &str) -> Result<(), String> {
         println!("logs        : {}", log_dir.display());
         println!("  stdout    : {}", log_dir.join("stdout.log").display());
         println!("  stderr    : {}", log_dir.join("stderr.log").display());
+        if config.logs.timestamps {
+            println!("  timestamps: yes");
+        }
+        if config.logs.max_size > 0 {
+            println!(
+                "  rotation  : every {}, {} file(s) kept{}",
+                format_size(config.logs.max_size),
+                config.logs.max_files,
+                if config.logs.compress { ", gzip" } else { "" }
+            );
+        }
+        if config.logs.keep_runs > 0 {
+            println!(
+                "  runs      : {} (last {} kept)",
+                log_dir.join("runs").display(),
+                config.logs.keep_runs
+            );
+        }
     } else {
         println!("logs        : -");
     }
 
     Ok(())
 }
-fn show_info(basedir: &PathBuf) -> Result<(), String> {
+fn show_info(manager: &Manager, output: Output) -> Result<(), Error> {
+    if output == Output::Json {
+        return print_json(&manager.info()?);
+    }
     println!("execmgr info");
     println!("-------------");
 
-    println!("base dir    : {}", basedir.display());
+    println!("base dir    : {}", manager.basedir().display());
 
-    let mut total = 0usize;
-    let mut running = 0usize;
+    let info = manager.info()?;
 
-    if basedir.exists() {
-        for entry in
-            std::fs::read_dir(basedir).map_err(|e| format!("unable to read base dir: {}", e))?
-        {
-            let entry = entry.map_err(|e| format!("error reading entry: {}", e))?;
-            if !entry.file_type().map_err(|e| e.to_string())?.is_dir() {
-                continue;
-            }
-
-            total += 1;
-
-            if check_running(&entry.path()) {
-                running += 1;
-            }
-        }
-    }
-
-    println!("apps        : {}", total);
-    println!("running     : {}", running);
+    println!("apps        : {}", info.apps);
+    println!("running     : {}", info.running);
 
     // binary info (best-effort)
-    if let Ok(exe) = std::env::current_exe() {
+    if let Some(exe) = &info.binary {
         println!("binary      : {}", exe.display());
     } else {
         println!("binary      : -");
     }
 
     println!("rust        : {}", env!("CARGO_PKG_NAME"));
-    println!("version     : {}", env!("CARGO_PKG_VERSION"));
+    println!("version     : {}", info.version);
 
     Ok(())
 }
-fn stop_app(basedir: &PathBuf, name: &str, force: bool) -> Result<(), String> {
-    let path = basedir.join(name);
-    if !path.exists() {
-        return Err(format!("app '{}' not exists.", name));
+fn stop_app(
+    manager: &Manager,
+    name: &AppName,
+    options: StopOptions,
+    output: Output,
+) -> Result<(), Error> {
+    if output == Output::Json {
+        return print_json(&manager.stop(name, options)?);
+    }
+    let result = manager.stop_with(name, options, |name, event| match event {
+        StopEvent::RunningScript => println!("running stop.sh for '{}'", name),
+        StopEvent::ScriptFailed(status) => {
+            eprintln!("stop.sh failed for app '{}' ({})", name, status)
+        }
+        StopEvent::ScriptTimedOut(timeout) => println!(
+            "'{}' still running {}s after stop.sh",
+            name,
+            timeout.as_secs()
+        ),
+        StopEvent::Signalling { signal, pid } => {
+            println!("sending {} to '{}' (pid {})", signal_name(signal), name, pid)
+        }
+        StopEvent::Escalating(timeout) => println!(
+            "'{}' did not stop within {}s, sending SIGKILL",
+            name,
+            timeout.as_secs()
+        ),
+    })?;
+    for stopped in result.dependents.iter().chain([&result]) {
+        let name = &stopped.name;
+        match &stopped.stopped {
+            Stopped::Script => println!("stopped '{}' (stop.sh)", name),
+            Stopped::Signal(signal) => println!("stopped '{}' ({})", name, signal_name(*signal)),
+            Stopped::Kill => println!("stopped '{}' (SIGKILL)", name),
+            Stopped::ForcedScript(status) => {
+                println!("'{}' stop script exited: {}", name, status)
+            }
+            Stopped::CancelledRestart => println!("cancelled pending restart of '{}'", name),
+        }
     }
-    let script = path.join("stop.sh");
-    if !script.exists() {
-        return Err(format!("'{:?}'  not found for '{}'.", script, name));
+    let running = &result.running_dependents;
+    if !running.is_empty() {
+        let names: Vec<String> = running.iter().map(|name| format!("'{}'", name)).collect();
+        eprintln!(
+            "warning: {} {} on '{}' and {} still running, use --cascade to stop {} too",
+            names.join(", "),
+            if running.len() == 1 { "depends" } else { "depend" },
+            name,
+            if running.len() == 1 { "is" } else { "are" },
+            if running.len() == 1 { "it" } else { "them" },
+        );
     }
-
-    if !force && !check_running(&path) {
-        return Err(format!("app '{}' is not running", name));
+    Ok(())
+}
+fn kill_app(manager: &Manager, name: &AppName, tree: bool, output: Output) -> Result<(), Error> {
+    let result = manager.kill(name, tree)?;
+    if output == Output::Json {
+        return print_json(&result);
     }
-
-    println!("stopped '{}'", name);
-    let status = run_attached(&script)?;
-
-    if !status.success() {
-        return Err(format!("stop.sh failed for app '{}'", name));
+    match result.killed {
+        Killed::Pid(pid) => println!("force killing '{}' (pid {})", name, pid),
+        Killed::CancelledRestart => println!("cancelled pending restart of '{}'", name),
     }
-    println!("exit: {}", status);
     Ok(())
 }
-fn kill_app(basedir: &PathBuf, name: &str) -> Result<(), String> {
-    let path = basedir.join(name);
-    if !path.exists() {
-        return Err(format!("app '{}' not exists.", name));
-    }
-    if !check_running(&path) {
-        return Err(format!("app '{}' is not running", name));
+fn show_history(
+    manager: &Manager,
+    name: &AppName,
+    limit: Option<usize>,
+    output: Output,
+) -> Result<(), Error> {
+    let runs = manager.history(name, limit)?;
+
+    match output {
+        Output::Json => return print_json(&runs),
+        Output::Plain => {
+            for run in runs {
+                print_plain(&[
+                    run.run.to_string(),
+                    run.started_at.clone(),
+                    run.ended_at.clone().unwrap_or_else(|| "-".into()),
+                    run.duration_secs
+                        .map(|d| d.to_string())
+                        .unwrap_or_else(|| "-".into()),
+                    run.exit_status().unwrap_or_else(|| "-".into()),
+                    run.pid.to_string(),
+                    run.started_by.to_string(),
+                ]);
+            }
+            return Ok(());
+        }
+        Output::Table => {}
     }
 
-    let json_path = path.join("app.json");
-    let app: App = serde_json::from_str(
-        &std::fs::read_to_string(&json_path)
-            .map_err(|_| format!("unable to read {:?}", json_path))?,
-    )
-    .map_err(|_| format!("data is corrupted in {:?}", json_path))?;
-
-    let last_run = if let Some(last_run) = app.last_run {
-        last_run
-    } else {
-        return Err(format!(
-            "no pidfile found for '{}'; cannot force kill safely",
-            name
-        ));
-    };
-
-    println!("force killing '{}' (pid {})", name, last_run.pid);
-
-    let status = kill_pid(last_run.pid)?;
-
-    if !status.success() {
-        return Err(format!("kill -9 failed for pid {}", last_run.pid));
+    println!(
+        "{:<6} {:<20} {:<20} {:<10} {:<8} {:<8} BY",
+        "RUN", "STARTED", "ENDED", "DURATION", "EXIT", "PID"
+    );
+    for run in runs {
+        println!(
+            "{:<6} {:<20} {:<20} {:<10} {:<8} {:<8} {}",
+            run.run,
+            run.started_at
+                .get(..19)
+                .unwrap_or(&run.started_at)
+                .replace('T', " "),
+            run.ended_at
+                .as_ref()
+                .map(|t| t.get(..19).unwrap_or(t).replace('T', " "))
+                .unwrap_or_else(|| "running".into()),
+            run.duration_secs
+                .map(|d| format!("{}s", d))
+                .unwrap_or_else(|| "-".into()),
+            run.exit_status().unwrap_or_else(|| "-".into()),
+            run.pid,
+            run.started_by
+        );
     }
-    println!("exit: {}", status);
-
     Ok(())
 }
-fn delete_app(basedir: &PathBuf, name: &str) -> Result<(), String> {
-    let path = basedir.join(name);
-    if !path.exists() {
-        return Err(format!("app '{}' not exists.", name));
+fn set_app(
+    manager: &Manager,
+    name: &AppName,
+    settings: Settings,
+    output: Output,
+) -> Result<(), Error> {
+    let config = manager.set(name, &settings)?;
+    if output == Output::Json {
+        return print_json(&config);
+    }
+
+    println!("settings for '{}'", name);
+    println!("restart     : {}", config.restart.mode);
+    println!("max retries : {}", config.restart.max_retries);
+    println!("backoff     : {}s", config.restart.backoff_secs);
+    println!("max backoff : {}s", config.restart.max_backoff_secs);
+    println!("reset after : {}s", config.restart.reset_after_secs);
+    println!("keep runs   : {}", config.logs.keep_runs);
+    println!("max log size: {}", format_size(config.logs.max_size));
+    println!("max logs    : {}", config.logs.max_files);
+    println!("compress    : {}", if config.logs.compress { "yes" } else { "no" });
+    println!("timestamps  : {}", if config.logs.timestamps { "yes" } else { "no" });
+    println!("stop script : {}", if config.stop.use_script { "yes" } else { "no" });
+    println!("stop signal : {}", config.stop.signal);
+    println!("stop timeout: {}s", config.stop.timeout_secs);
+    println!("descendants : {}", if config.stop.descendants { "yes" } else { "no" });
+    match &config.ready.probe {
+        Some(probe) => println!("ready       : {} (timeout {}s)", probe, config.ready.timeout_secs),
+        None => println!("ready       : -"),
+    }
+    match &config.health.probe {
+        Some(probe) => {
+            println!("health      : {}", probe);
+            if matches!(probe, HealthProbe::Http(_)) {
+                println!("health code : {}", config.health.status);
+            }
+            println!("interval    : {}s", config.health.interval_secs);
+            println!("timeout     : {}s", config.health.timeout_secs);
+            println!("threshold   : {}", config.health.threshold);
+            println!(
+                "on unhealthy: {}",
+                if config.health.restart { "restart" } else { "report" }
+            );
+        }
+        None => println!("health      : -"),
+    }
+    let names: Vec<&str> = config.env.keys().map(String::as_str).collect();
+    println!("env         : {}", if names.is_empty() { "-".into() } else { names.join(", ") });
+    let files: Vec<String> = config
+        .env_files
+        .iter()
+        .map(|file| file.display().to_string())
+        .collect();
+    println!("env files   : {}", if files.is_empty() { "-".into() } else { files.join(", ") });
+    println!("clean env   : {}", if config.clean_env { "yes" } else { "no" });
+    println!("cwd         : {}", config.cwd.as_deref().unwrap_or(Path::new(".")).display());
+    let names: Vec<&str> = config.depends_on.iter().map(AppName::as_str).collect();
+    println!("depends on  : {}", if names.is_empty() { "-".into() } else { names.join(", ") });
+    Ok(())
+}
+fn show_env(manager: &Manager, name: &AppName, reveal: bool, output: Output) -> Result<(), Error> {
+    let vars = manager.env(name, reveal)?;
+    match output {
+        Output::Json => print_json(&vars)?,
+        Output::Plain => {
+            for var in vars {
+                print_plain(&[var.name, var.value, var.source]);
+            }
+        }
+        Output::Table => {
+            for var in vars {
+                println!("{}={}", var.name, var.value);
+            }
+        }
     }
-    if check_running(&path) {
-        return Err(format!("can't delete, app '{}' is running", name));
+    Ok(())
+}
+fn delete_app(manager: &Manager, name: &AppName, force: bool, output: Output) -> Result<(), Error> {
+    let result = manager.delete(name, force)?;
+    if output == Output::Json {
+        return print_json(&result);
     }
-    remove_dir_all(path).expect("unable to delete");
     println!("deleted '{}'", name);
     Ok(())
 }
-fn list_app(basedir: &PathBuf, long: bool, full: bool) -> Result<(), String> {
-    if long {
-        println!(
-            "{:<20} {:<20} {:<25} {:<6} {:<8} {:<25} {}",
-            "NAME", "PATH", "CREATED", "RUNS", "PID", "LAST_RUN", "RUNNING"
-        );
+fn list_app(manager: &Manager, long: bool, full: bool, output: Output) -> Result<(), Error> {
+    if output == Output::Json {
+        return print_json(&manager.list()?);
     }
-
-    if !basedir.exists() {
+    if !long {
+        for name in manager.names()? {
+            println!("{}", name);
+        }
         return Ok(());
     }
-    for entry in read_dir(basedir).map_err(|_| "unable to read dir")? {
-        let entry = entry.map_err(|_| "error while reading entry")?;
-        let entry_type = entry
-            .file_type()
-            .map_err(|_| "error while reading entry type")?;
-        if !entry_type.is_dir() {
-            eprintln!("Unknow app: {}", entry.path().display());
+
+    if output == Output::Table {
+        println!(
+            "{:<20} {:<20} {:<25} {:<6} {:<8} {:<25} {:<8} RUNNING",
+            "NAME", "PATH", "CREATED", "RUNS", "PID", "LAST_RUN", "EXIT"
+        );
+    }
+    for status in manager.list()? {
+        warn_config_error(&status);
+        let app = &status.app;
+        if output == Output::Plain {
+            print_plain(&[
+                status.name.clone(),
+                status.path.display().to_string(),
+                app.created_at.clone(),
+                app.num_runs.to_string(),
+                app.last_run
+                    .as_ref()
+                    .map(|l| l.pid.to_string())
+                    .unwrap_or_else(|| "-".into()),
+                app.last_run
+                    .as_ref()
+                    .map(|l| l.time.clone())
+                    .unwrap_or_else(|| "-".into()),
+                app.last_run
+                    .as_ref()
+                    .and_then(|l| l.exit_status())
+                    .unwrap_or_else(|| "-".into()),
+                if status.running { "yes" } else { "no" }.to_string(),
+            ]);
             continue;
         }
-
-        if long {
-            let path = entry.path();
-            let json_path = path.join("app.json");
-            let name = entry
-                .file_name()
-                .to_str()
-                .expect("bug: unknown encoding")
-                .to_owned();
-            let app: App = serde_json::from_str(
-                &std::fs::read_to_string(&json_path)
-                    .map_err(|_| format!("unable to read {:?}", json_path))?,
-            )
-            .map_err(|_| format!("data is corrupted in {:?}", json_path))?;
-
-            let is_running = check_running(&path);
-            if full {
-                println!(
-                    "{:<20} {:<20} {:<25} {:<6} {:<8} {:<25} {}",
-                    name,
-                    path.display(),
-                    app.created_at
-                        .get(..19)
-                        .unwrap_or(&app.created_at)
-                        .replace('T', " "),
-                    app.num_runs,
-                    app.last_run
-                        .as_ref()
-                        .map(|l| l.pid.to_string())
-                        .unwrap_or_else(|| "-".into()),
-                    app.last_run
-                        .map(|last_run| last_run
-                            .time
-                            .get(..19)
-                            .unwrap_or(&last_run.time)
-                            .replace('T', " "))
-                        .unwrap_or_else(|| "-".into()),
-                    if is_running { "yes" } else { "no" }
-                );
-            } else {
-                println!(
-                    "{:<20.20} {:<20.20} {:<25.25} {:<6} {:<8} {:<25.25} {}",
-                    name,
-                    path.display(),
-                    app.created_at
-                        .get(..19)
-                        .unwrap_or(&app.created_at)
-                        .replace('T', " "),
-                    app.num_runs,
-                    app.last_run
-                        .as_ref()
-                        .map(|l| l.pid.to_string())
-                        .unwrap_or_else(|| "-".into()),
-                    app.last_run
-                        .map(|last_run| last_run
-                            .time
-                            .get(..19)
-                            .unwrap_or(&last_run.time)
-                            .replace('T', " "))
-                        .unwrap_or_else(|| "-".into()),
-                    if is_running { "yes" } else { "no" }
-                );
-            }
+        let created = app
+            .created_at
+            .get(..19)
+            .unwrap_or(&app.created_at)
+            .replace('T', " ");
+        let pid = app
+            .last_run
+            .as_ref()
+            .map(|l| l.pid.to_string())
+            .unwrap_or_else(|| "-".into());
+        let last_run = app
+            .last_run
+            .as_ref()
+            .map(|last_run| last_run.time.get(..19).unwrap_or(&last_run.time).replace('T', " "))
+            .unwrap_or_else(|| "-".into());
+        let exit = app
+            .last_run
+            .as_ref()
+            .and_then(|l| l.exit_status())
+            .unwrap_or_else(|| "-".into());
+        let running = if status.running { "yes" } else { "no" };
+        if full {
+            println!(
+                "{:<20} {:<20} {:<25} {:<6} {:<8} {:<25} {:<8} {}",
+                status.name,
+                status.path.display(),
+                created,
+                app.num_runs,
+                pid,
+                last_run,
+                exit,
+                running
+            );
         } else {
-            println!("{}", entry.file_name().display());
+            println!(
+                "{:<20.20} {:<20.20} {:<25.25} {:<6} {:<8} {:<25.25} {:<8.8} {}",
+                status.name,
+                status.path.display(),
+                created,
+                app.num_runs,
+                pid,
+                last_run,
+                exit,
+                running
+            );
         }
     }
     Ok(())
 }
-fn list_process(basedir: &PathBuf, long: bool, full: bool) -> Result<(), String> {
-    if long {
+/// The app is still listed with the default settings
+fn warn_config_error(status: &AppStatus) {
+    if let Some(error) = &status.config_error {
+        eprintln!("warning: '{}': {}", status.name, error);
+    }
+}
+fn list_process(manager: &Manager, long: bool, full: bool, output: Output) -> Result<(), Error> {
+    // apps waiting for a restart are still owned by their supervisor
+    let apps = manager
+        .list()?
+        .into_iter()
+        .filter(|status| status.running || status.pending_restart);
+    if output == Output::Json {
+        return print_json(&apps.collect::<Vec<_>>());
+    }
+    if long && output == Output::Table {
         println!(
-            "{:<20} {:<20} {:<25} {:<6} {:<8} {:<25}",
-            "NAME", "PATH", "CREATED", "RUNS", "PID", "UPTIME"
+            "{:<20} {:<20} {:<25} {:<6} {:<8} {:<10} {:<10} {:<8} NEXT_RETRY",
+            "NAME", "PATH", "CREATED", "RUNS", "PID", "UPTIME", "HEALTH", "RESTARTS"
         );
     }
-    if !basedir.exists() {
-        return Ok(());
-    }
-    for entry in read_dir(basedir).map_err(|_| "unable to read dir")? {
-        let entry = entry.map_err(|_| "error while reading entry")?;
-        let entry_type = entry
-            .file_type()
-            .map_err(|_| "error while reading entry type")?;
-        if !entry_type.is_dir() {
-            eprintln!("Unknow app: {}", entry.path().display());
+    for status in apps {
+        warn_config_error(&status);
+        if !long {
+            println!("{}", status.name);
             continue;
         }
-
-        let name = entry
-            .file_name()
-            .to_str()
-            .expect("bug: unknown encoding")
-            .to_owned();
-        let path = entry.path();
-        if !check_running(&path) {
+        let app = &status.app;
+        if output == Output::Plain {
+            print_plain(&[
+                status.name.clone(),
+                status.path.display().to_string(),
+                app.created_at.clone(),
+                app.num_runs.to_string(),
+                status
+                    .pid
+                    .map(|pid| pid.to_string())
+                    .unwrap_or_else(|| "-".into()),
+                status
+                    .uptime_secs
+                    .map(|value| value.to_string())
+                    .unwrap_or_else(|| "-".into()),
+                status
+                    .health
+                    .map(|health| health.to_string())
+                    .unwrap_or_else(|| "-".into()),
+                app.restarts.count.to_string(),
+                app.restarts
+                    .next_retry_at
+                    .clone()
+                    .unwrap_or_else(|| "-".into()),
+            ]);
             continue;
         }
-        if long {
-            let json_path = path.join("app.json");
-            let app: App = serde_json::from_str(
-                &std::fs::read_to_string(&json_path)
-                    .map_err(|_| format!("unable to read {:?}", json_path))?,
-            )
-            .map_err(|_| format!("data is corrupted in {:?}", json_path))?;
-            if full {
-                println!(
-                    "{:<20} {:<20} {:<25} {:<6} {:<8} {:<25}",
-                    name,
-                    path.display(),
-                    app.created_at
-                        .get(..19)
-                        .unwrap_or(&app.created_at)
-                        .replace('T', " "),
-                    app.num_runs,
-                    app.last_run
-                        .as_ref()
-                        .map(|l| l.pid.to_string())
-                        .unwrap_or_else(|| "-".into()),
-                    app.last_run
-                        .as_ref()
-                        .map(|l| {
-                            since_running(&l.time)
-                                .map(|value| value.to_string())
-                                .unwrap_or_else(|| "-".into())
-                        })
-                        .unwrap_or_else(|| "-".into()),
-                );
-            } else {
-                println!(
-                    "{:<20.20} {:<20.20} {:<25.25} {:<6} {:<8} {:<25.25}",
-                    name,
-                    path.display(),
-                    app.created_at
-                        .get(..19)
-                        .unwrap_or(&app.created_at)
-                        .replace('T', " "),
-                    app.num_runs,
-                    app.last_run
-                        .as_ref()
-                        .map(|l| l.pid.to_string())
-                        .unwrap_or_else(|| "-".into()),
-                    app.last_run
-                        .as_ref()
-                        .map(|l| {
-                            since_running(&l.time)
-                                .map(|value| value.to_string())
-                                .unwrap_or_else(|| "-".into())
-                        })
-                        .unwrap_or_else(|| "-".into())
-                );
-            }
+        let created = app
+            .created_at
+            .get(..19)
+            .unwrap_or(&app.created_at)
+            .replace('T', " ");
+        let pid = app
+            .last_run
+            .as_ref()
+            .map(|l| l.pid.to_string())
+            .unwrap_or_else(|| "-".into());
+        let uptime = status
+            .uptime_secs
+            .map(|value| value.to_string())
+            .unwrap_or_else(|| "-".into());
+        let health = status
+            .health
+            .map(|health| health.to_string())
+            .unwrap_or_else(|| "-".into());
+        let next_retry = app
+            .restarts
+            .next_retry_at
+            .as_ref()
+            .map(|t| t.get(..19).unwrap_or(t).replace('T', " "))
+            .unwrap_or_else(|| "-".into());
+        if full {
+            println!(
+                "{:<20} {:<20} {:<25} {:<6} {:<8} {:<10} {:<10} {:<8} {}",
+                status.name,
+                status.path.display(),
+                created,
+                app.num_runs,
+                pid,
+                uptime,
+                health,
+                app.restarts.count,
+                next_retry,
+            );
         } else {
-            println!("{}", name);
+            println!(
+                "{:<20.20} {:<20.20} {:<25.25} {:<6} {:<8} {:<10.10} {:<10} {:<8} {}",
+                status.name,
+                status.path.display(),
+                created,
+                app.num_runs,
+                pid,
+                uptime,
+                health,
+                app.restarts.count,
+                next_retry,
+            );
         }
     }
 
     Ok(())
 }
-fn clear_logs(basedir: &PathBuf, name: &str, stderr: bool, stdout: bool) -> Result<(), String> {
-    let path = basedir.join(name);
-    if !path.exists() {
-        return Err(format!("app '{}' not exists.", name));
+fn clear_logs(
+    manager: &Manager,
+    name: &AppName,
+    stderr: bool,
+    stdout: bool,
+    output: Output,
+) -> Result<(), Error> {
+    let result = manager.clear_logs(name, stdout, stderr)?;
+    if output == Output::Json {
+        return print_json(&result);
     }
-    let logs = log_paths(&path)?;
     if stdout {
-        if logs.stdout.exists() {
-            std::fs::write(&logs.stdout, "")
-                .map_err(|e| format!("failed to clear {:?}: {}", logs.stdout, e))?;
-        }
         println!("stdout logs cleared for '{}'", name);
     }
     if stderr {
-        if logs.stderr.exists() {
-            std::fs::write(&logs.stderr, "")
-                .map_err(|e| format!("failed to clear {:?}: {}", logs.stderr, e))?;
-        }
         println!("stderr logs cleared for '{}'", name);
     }
 
     Ok(())
 }
+fn print_lines(lines: Vec<LogLine>, show_prefix: bool, output: Output) {
+    for line in lines {
+        if output == Output::Json {
+            // one object per line, so followed logs can be piped into `jq`
+            println!("{}", serde_json::to_string(&line).unwrap_or_default());
+        } else if show_prefix {
+            println!("[{}] {}", line.stream, line.text);
+        } else {
+            println!("{}", line.text);
+        }
+    }
+}
 fn show_logs(
-    basedir: &PathBuf,
-    name: &str,
-    stdout: bool,
-    stderr: bool,
+    manager: &Manager,
+    name: &AppName,
+    query: LogQuery,
     follow: bool,
     exit_on_stopped: bool,
-) -> Result<(), String> {
-    let path = basedir.join(name);
-    if !path.exists() {
-        return Err(format!("app '{}' not exists.", name));
-    }
-
-    let logs = log_paths(&path)?;
-    if !logs.stdout.exists() && !logs.stderr.exists() {
-        return Err("no logs found (app may not have been run yet)".into());
-    }
-
-    let (show_stdout, show_stderr) = if follow {
-        match (stdout, stderr) {
-            (false, false) => (true, true),
-            (s_out, s_err) => (s_out, s_err),
-        }
-    } else {
-        match (stdout, stderr) {
-            (false, false) => (true, false),
-            (s_out, s_err) => (s_out, s_err),
-        }
+    output: Output,
+) -> Result<(), Error> {
+    // nothing after `until` can show up by following, earlier runs are done
+    let follow = follow && query.until.is_none() && query.run.is_none();
+    let (stdout, stderr) = match (query.stdout, query.stderr) {
+        // everything when following, stdout only when dumping
+        (false, false) => (true, follow),
+        streams => streams,
+    };
+    let query = LogQuery {
+        stdout,
+        stderr,
+        ..query
     };
+    let show_prefix = query.merged || (stdout && stderr);
 
     if follow {
-        use std::fs::File;
-        use std::io::{Read, Seek, SeekFrom};
-        use std::thread;
-        use std::time::Duration;
-
-        struct Follower {
-            file: File,
-            buffer: Vec<u8>,
-            prefix: &'static str,
-            show_prefix: bool,
-        }
-
-        impl Follower {
-            fn new(path: &PathBuf, prefix: &'static str, show_prefix: bool) -> Result<Self, std::io::Error> {
-                let mut file = File::open(path)?;
-                let content = std::fs::read_to_string(path).unwrap_or_default();
-                let lines: Vec<&str> = content.lines().collect();
-                let start_idx = lines.len().saturating_sub(10);
-                for line in &lines[start_idx..] {
-                    if show_prefix {
-                        println!("{} {}", prefix, line);
-                    } else {
-                        println!("{}", line);
-                    }
-                }
-                file.seek(SeekFrom::End(0))?;
-                Ok(Follower {
-                    file,
-                    buffer: Vec::new(),
-                    prefix,
-                    show_prefix,
-                })
-            }
-
-            fn read_new_lines(&mut self) -> Result<bool, std::io::Error> {
-                let mut temp_buf = [0u8; 4096];
-                let bytes_read = self.file.read(&mut temp_buf)?;
-                if bytes_read == 0 {
-                    return Ok(false);
-                }
-
-                self.buffer.extend_from_slice(&temp_buf[..bytes_read]);
-                let mut start = 0;
-                for i in 0..self.buffer.len() {
-                    if self.buffer[i] == b'\n' {
-                        let line_bytes = &self.buffer[start..i];
-                        let line = String::from_utf8_lossy(line_bytes);
-                        if self.show_prefix {
-                            println!("{} {}", self.prefix, line);
-                        } else {
-                            println!("{}", line);
-                        }
-                        start = i + 1;
-                    }
-                }
-                if start > 0 {
-                    self.buffer.drain(0..start);
-                }
-                Ok(true)
-            }
-        }
-
-        let mut followers = Vec::new();
-        let show_prefix = show_stdout && show_stderr;
-
-        if show_stdout && logs.stdout.exists() {
-            followers.push(Follower::new(&logs.stdout, "[stdout]", show_prefix)
-                .map_err(|e| format!("failed to follow stdout: {}", e))?);
-        }
-        if show_stderr && logs.stderr.exists() {
-            followers.push(Follower::new(&logs.stderr, "[stderr]", show_prefix)
-                .map_err(|e| format!("failed to follow stderr: {}", e))?);
-        }
-
-        if followers.is_empty() {
-            return Err("no log files could be opened".into());
-        }
-
-        let mut has_seen_running = false;
-        let mut startup_checks = 10;
-
-        loop {
-            let mut read_anything = false;
-            for follower in &mut followers {
-                match follower.read_new_lines() {
-                    Ok(true) => {
-                        read_anything = true;
-                    }
-                    Ok(false) => {}
-                    Err(e) => {
-                        return Err(format!("error reading logs: {}", e));
-                    }
-                }
-            }
-            if !read_anything {
-                if exit_on_stopped {
-                    let is_running = check_running(&path);
-                    if is_running {
-                        has_seen_running = true;
-                    } else if has_seen_running {
-                        break;
-                    } else if startup_checks > 0 {
-                        startup_checks -= 1;
-                    } else {
-                        break;
-                    }
-                }
-                thread::sleep(Duration::from_millis(100));
-            } else if exit_on_stopped {
-                has_seen_running = true;
-            }
-        }
+        manager.follow_logs(name, &query, 10, exit_on_stopped, |lines| {
+            print_lines(lines, show_prefix, output);
+            true
+        })
     } else {
-        if show_stdout && show_stderr {
-            if logs.stdout.exists() {
-                let content = std::fs::read_to_string(&logs.stdout)
-                    .map_err(|e| format!("failed to read stdout log: {}", e))?;
-                for line in content.lines() {
-                    println!("[stdout] {}", line);
-                }
-            }
-            if logs.stderr.exists() {
-                let content = std::fs::read_to_string(&logs.stderr)
-                    .map_err(|e| format!("failed to read stderr log: {}", e))?;
-                for line in content.lines() {
-                    println!("[stderr] {}", line);
-                }
-            }
-        } else if show_stdout {
-            if logs.stdout.exists() {
-                let content = std::fs::read_to_string(&logs.stdout)
-                    .map_err(|e| format!("failed to read stdout log: {}", e))?;
-                print!("{}", content);
-            }
-        } else if show_stderr {
-            if logs.stderr.exists() {
-                let content = std::fs::read_to_string(&logs.stderr)
-                    .map_err(|e| format!("failed to read stderr log: {}", e))?;
-                print!("{}", content);
-            }
-        }
+        print_lines(manager.logs(name, &query)?, show_prefix, output);
+        Ok(())
     }
-    Ok(())
 }
 fn main() {
-    let cli = cli::Cli::try_parse().unwrap_or_else(|e| {
-        e.print().expect("Unable to print error");
-        std::process::exit(1);
-    });
+    let cli = cli::Cli::try_parse().unwrap_or_else(|e| e.exit());
 
-    let basedir = resolve_base_dir();
+    let manager = match std::env::current_exe() {
+        Ok(exe) => Manager::from_env().with_executable(exe),
+        Err(_) => Manager::from_env(),
+    };
+    let output = cli.output;
 
     let result = match cli.command {
-        Commands::Info => show_info(&basedir),
-        Commands::Create { name } => create_app(&basedir, &name),
-        Commands::Status { name } => status_app(&basedir, &name),
-        Commands::Run { name, detached } => match run_app(&basedir, &name) {
+        Commands::Info => show_info(&manager, output),
+        Commands::Create { name, command } => create_app(&manager, &name, command, output),
+        Commands::Status { name } => status_app(&manager, &name, output),
+        Commands::Run {
+            name,
+            detached,
+            wait_ready,
+            no_deps,
+        } => match run_app(&manager, &name, wait_ready, no_deps, output) {
             Err(e) => Err(e),
             _ => {
                 if detached {
                     Ok(())
                 } else {
-                    show_logs(&basedir, &name, true, true, true, true)
+                    show_logs(
+                        &manager,
+                        &name,
+                        LogQuery {
+                            stdout: true,
+                            stderr: true,
+                            ..LogQuery::default()
+                        },
+                        true,
+                        true,
+                        output,
+                    )
                 }
             }
         },
-        Commands::Stop { name, force } => stop_app(&basedir, &name, force),
-        Commands::Kill { name } => kill_app(&basedir, &name),
-        Commands::List { long, full } => list_app(&basedir, long, full),
-        Commands::Ps { long, full } => list_process(&basedir, long, full),
+        Commands::Stop {
+            name,
+            force,
+            timeout,
+            no_script,
+            tree,
+            cascade,
+        } => stop_app(
+            &manager,
+            &name,
+            StopOptions {
+                force,
+                timeout,
+                no_script,
+                tree,
+                cascade,
+                capture: false,
+            },
+            output,
+        ),
+        Commands::Set { name, settings } => set_app(&manager, &name, *settings, output),
+        Commands::Env { name, reveal } => show_env(&manager, &name, reveal, output),
+        Commands::Supervise { name, origin } => manager.supervise(&name, origin),
+        Commands::Serve {
+            bind,
+            static_dir,
+            allow_origins,
+        } => server::serve(&manager, &bind, static_dir, allow_origins),
+        Commands::Listen { socket } => {
+            let socket = socket.unwrap_or_else(|| control::socket_path(&manager));
+            control::listen(&manager, &socket)
+        }
+        Commands::Daemon { socket } => {
+            let socket = socket.unwrap_or_else(|| control::socket_path(&manager));
+            control::listen(&manager.clone().supervise_in_process(), &socket)
+        }
+        Commands::History { name, limit, json } => {
+            let output = if json { Output::Json } else { output };
+            show_history(&manager, &name, limit, output)
+        }
+        Commands::Kill { name, tree } => kill_app(&manager, &name, tree, output),
+        Commands::List { long, full } => list_app(&manager, long, full, output),
+        Commands::Ps { long, full } => list_process(&manager, long, full, output),
         Commands::Log {
             name,
             clear,
             stderr,
             stdout,
             no_follow,
+            run,
+            since,
+            until,
+            merged,
         } => {
             if clear {
                 if !stderr && !stdout {
-                    clear_logs(&basedir, &name, true, true)
+                    clear_logs(&manager, &name, true, true, output)
                 } else {
-                    clear_logs(&basedir, &name, stderr, stdout)
+                    clear_logs(&manager, &name, stderr, stdout, output)
                 }
             } else {
-                show_logs(&basedir, &name, stdout, stderr, !no_follow, false)
+                show_logs(
+                    &manager,
+                    &name,
+                    LogQuery {
+                        stdout,
+                        stderr,
+                        run,
+                        since,
+                        until,
+                        merged,
+                    },
+                    !no_follow,
+                    false,
+                    output,
+                )
             }
         }
         Commands::Delete { name, force } => {
@@ -736,17 +901,21 @@ fn main() {
                 let mut input = String::new();
                 io::stdin().read_line(&mut input).ok();
                 if !matches!(input.trim(), "y" | "Y") {
-                    Err("delete aborted".to_string())
+                    Err(Error::Failed("delete aborted".to_string()))
                 } else {
-                    delete_app(&basedir, &name)
+                    delete_app(&manager, &name, force, output)
                 }
             } else {
-                delete_app(&basedir, &name)
+                delete_app(&manager, &name, force, output)
             }
         }
     };
     if let Err(e) = result {
-        eprintln!("Error: {}", e);
-        std::process::exit(1);
+        if output == Output::Json {
+            eprintln!("{}", json!({ "error": e.to_string(), "code": e.exit_code() }));
+        } else {
+            eprintln!("Error: {}", e);
+        }
+        std::process::exit(e.exit_code());
     }
 }
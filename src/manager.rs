@@ -0,0 +1,1678 @@
+use std::fmt;
+use std::fs::{File, OpenOptions, create_dir_all, read_dir, remove_dir_all};
+use std::io::{self, BufRead, Read, Seek, Write};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use clap::Args;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::app::{
+    App, AppName, Health, HealthProbe, HealthState, Origin, ReadyProbe, RestartMode, RestartState,
+    RunRecord, signal_name,
+};
+use crate::config::{
+    AppConfig, CONFIG_FILE, dependency_order, initial_config, read_config, write_config,
+};
+use crate::env::{EnvVar, env_map, resolve_env};
+use crate::error::Error;
+use crate::health;
+use crate::history::read_history;
+use crate::logs::{
+    filter_lines, line_time, read_log, read_segments, remove_segments, strip_timestamp,
+};
+use crate::supervisor::{supervise, supervise_to};
+use crate::utils::{
+    LogPath, check_running, check_supervised, log_paths, parse_signal, parse_size, read_app,
+    resolve_base_dir, run_attached, run_captured, run_log_paths, signal_app, since_running,
+    update_app, verify_pid, wait_stopped, wait_unsupervised, work_dir,
+};
+
+/// Lines of stderr.log shown when an app does not get ready
+const STDERR_TAIL: usize = 10;
+/// How long stop/kill/run wait for a supervisor that was told to stop to record the exit
+const SUPERVISOR_EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Entry point for embedding execmgr: every command of the CLI as a method
+/// returning structured values instead of printing.
+#[derive(Debug, Clone)]
+pub struct Manager {
+    basedir: PathBuf,
+    exe: Option<PathBuf>,
+    /// supervise apps on threads of this process instead of `execmgr supervise`
+    in_process: bool,
+}
+
+/// State of one app as shown by `status`, `ls` and `ps`, also the JSON schema
+/// of those commands
+#[derive(Debug, Serialize)]
+pub struct AppStatus {
+    pub name: String,
+    pub path: PathBuf,
+    pub running: bool,
+    /// down, but its supervisor is going to restart it
+    pub pending_restart: bool,
+    /// pid of the running app
+    pub pid: Option<u32>,
+    pub uptime_secs: Option<i64>,
+    /// result of the health checks while running, `None` without a health check
+    pub health: Option<Health>,
+    /// runtime state from app.json
+    pub app: App,
+    /// settings from app.toml, the defaults when it is invalid
+    pub config: AppConfig,
+    /// why app.toml could not be read, only set by `list`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Info {
+    pub basedir: PathBuf,
+    pub apps: usize,
+    pub running: usize,
+    pub binary: Option<PathBuf>,
+    pub version: &'static str,
+    /// apps are supervised on threads of this process, as by the daemon
+    pub daemon: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunInfo {
+    pub name: String,
+    pub pid: u32,
+    pub started_at: String,
+    /// seconds until the readiness probe passed, when that was waited for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ready_secs: Option<f64>,
+    /// dependencies that were started first, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<RunInfo>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RunOptions {
+    /// don't start the apps of depends_on first
+    pub no_deps: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Scripts {
+    pub start: String,
+    pub stop: String,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StopOptions {
+    /// only run stop.sh, even if the app is not running
+    pub force: bool,
+    /// seconds to wait for each stage, defaults to the app's stop timeout
+    pub timeout: Option<u64>,
+    pub no_script: bool,
+    /// also signal descendants that left the process group
+    pub tree: bool,
+    /// stop the running apps that depend on it first
+    pub cascade: bool,
+    /// return the output of stop.sh in the result instead of inheriting stdio
+    pub capture: bool,
+}
+
+/// Progress of `stop`, reported while it waits
+#[derive(Debug)]
+pub enum StopEvent {
+    RunningScript,
+    ScriptFailed(ExitStatus),
+    ScriptTimedOut(Duration),
+    Signalling { signal: i32, pid: u32 },
+    Escalating(Duration),
+}
+
+/// Outcome of `stop`, `{"name", "stopped_by"}` in JSON
+#[derive(Debug, Serialize)]
+pub struct StopResult {
+    pub name: String,
+    #[serde(rename = "stopped_by", serialize_with = "serialize_display")]
+    pub stopped: Stopped,
+    /// dependents stopped first because of `cascade`, in order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dependents: Vec<StopResult>,
+    /// dependents left running without `cascade`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub running_dependents: Vec<String>,
+    /// stop.sh as run with `capture`, `None` when it didn't run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub script: Option<ScriptOutput>,
+}
+
+/// A captured run of stop.sh
+#[derive(Debug, Serialize)]
+pub struct ScriptOutput {
+    /// `None` when it was killed by a signal
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+fn serialize_display<S: serde::Serializer>(
+    value: &impl fmt::Display,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+/// What ended the app on `stop`
+#[derive(Debug)]
+pub enum Stopped {
+    Script,
+    Signal(i32),
+    Kill,
+    /// the app was not running, stop.sh was run because of `force`
+    ForcedScript(ExitStatus),
+    /// the app was down, its pending restart got cancelled
+    CancelledRestart,
+}
+
+impl fmt::Display for Stopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stopped::Script => f.write_str("stop.sh"),
+            Stopped::Signal(signal) => f.write_str(&signal_name(*signal)),
+            Stopped::Kill => f.write_str("SIGKILL"),
+            Stopped::ForcedScript(_) => f.write_str("stop.sh (forced)"),
+            Stopped::CancelledRestart => f.write_str("restart cancelled"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Killed {
+    Pid(u32),
+    CancelledRestart,
+}
+
+/// Outcome of `kill`, `{"name", "killed", "pid"}` in JSON where `killed` is
+/// false (and `pid` null) when only a pending restart got cancelled
+#[derive(Debug)]
+pub struct KillResult {
+    pub name: String,
+    pub killed: Killed,
+}
+
+impl Serialize for KillResult {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let pid = match self.killed {
+            Killed::Pid(pid) => Some(pid),
+            Killed::CancelledRestart => None,
+        };
+        let mut result = serializer.serialize_struct("KillResult", 3)?;
+        result.serialize_field("name", &self.name)?;
+        result.serialize_field("killed", &pid.is_some())?;
+        result.serialize_field("pid", &pid)?;
+        result.end()
+    }
+}
+
+/// Outcome of `delete`, `{"name", "deleted": true}` in JSON
+#[derive(Debug, Serialize)]
+pub struct DeleteResult {
+    pub name: String,
+    /// always true, failures are errors
+    pub deleted: bool,
+}
+
+/// Outcome of `clear_logs`, `{"name", "cleared": ["stdout", ...]}` in JSON
+#[derive(Debug, Serialize)]
+pub struct ClearResult {
+    pub name: String,
+    pub cleared: Vec<Stream>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl fmt::Display for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogLine {
+    pub stream: Stream,
+    /// timestamp of the line, or of the last timestamped line above it
+    pub time: Option<DateTime<Local>>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LogQuery {
+    pub stdout: bool,
+    pub stderr: bool,
+    /// logs of an earlier run instead of the current one
+    pub run: Option<u64>,
+    pub since: Option<DateTime<Local>>,
+    pub until: Option<DateTime<Local>>,
+    /// both streams, interleaved by timestamp
+    pub merged: bool,
+}
+
+impl LogQuery {
+    fn streams(&self) -> (bool, bool) {
+        if self.merged {
+            (true, true)
+        } else {
+            (self.stdout, self.stderr)
+        }
+    }
+}
+
+/// Changes made by `set`, unset fields are left alone
+#[derive(Debug, Clone, Default, Args, Deserialize)]
+pub struct Settings {
+    /// When to restart the app after it exits
+    #[arg(long, value_enum)]
+    pub restart: Option<RestartMode>,
+
+    /// Give up after this many consecutive restarts (0 = retry forever)
+    #[arg(long)]
+    pub max_retries: Option<u32>,
+
+    /// Seconds to wait before the first restart, doubled on every retry
+    #[arg(long)]
+    pub backoff: Option<u64>,
+
+    /// Upper bound for the restart delay in seconds
+    #[arg(long)]
+    pub max_backoff: Option<u64>,
+
+    /// Reset the retry counter once a run stays up this many seconds
+    #[arg(long)]
+    pub reset_after: Option<u64>,
+
+    /// Keep the logs of the last N runs in logs/runs (0 = truncate on every run)
+    #[arg(long)]
+    pub keep_runs: Option<u32>,
+
+    /// Rotate a log once it grows past this size, e.g. 512K or 10M (0 = never)
+    #[arg(long, value_parser = parse_size)]
+    pub max_log_size: Option<u64>,
+
+    /// Number of rotated log files to keep
+    #[arg(long)]
+    pub max_log_files: Option<u32>,
+
+    /// Gzip rotated log files
+    #[arg(long)]
+    pub compress_logs: Option<bool>,
+
+    /// Prefix every log line with an RFC3339 timestamp
+    #[arg(long)]
+    pub timestamps: Option<bool>,
+
+    /// Run stop.sh (when present) before signalling the app on stop
+    #[arg(long)]
+    pub stop_script: Option<bool>,
+
+    /// Signal sent on stop, e.g. TERM, INT or HUP
+    #[arg(long)]
+    pub stop_signal: Option<String>,
+
+    /// Seconds stop waits for each stage before escalating to SIGKILL
+    #[arg(long)]
+    pub stop_timeout: Option<u64>,
+
+    /// Always walk /proc on stop/kill for descendants that left the process group
+    #[arg(long)]
+    pub kill_descendants: Option<bool>,
+
+    /// Health check: cmd:<command>, tcp:[host:]port, http://host[:port]/path or none
+    #[arg(long)]
+    pub health: Option<String>,
+
+    /// HTTP status an http health check expects
+    #[arg(long)]
+    pub health_status: Option<u16>,
+
+    /// Seconds between health checks
+    #[arg(long)]
+    pub health_interval: Option<u64>,
+
+    /// Seconds before a health check counts as failed
+    #[arg(long)]
+    pub health_timeout: Option<u64>,
+
+    /// Consecutive failed checks before the app is unhealthy
+    #[arg(long)]
+    pub health_threshold: Option<u32>,
+
+    /// Restart the app once it is unhealthy
+    #[arg(long)]
+    pub health_restart: Option<bool>,
+
+    /// Set an environment variable for the app (repeatable)
+    #[arg(long, value_name = "KEY=VALUE")]
+    #[serde(default)]
+    pub env: Vec<String>,
+
+    /// Remove an environment variable set with --env (repeatable)
+    #[arg(long, value_name = "KEY")]
+    #[serde(default)]
+    pub unset_env: Vec<String>,
+
+    /// Load a .env file, relative to the app dir (repeatable)
+    #[arg(long, value_name = "PATH")]
+    #[serde(default)]
+    pub env_file: Vec<PathBuf>,
+
+    /// Only pass the variables of pass_env through from execmgr's environment
+    #[arg(long)]
+    pub clean_env: Option<bool>,
+
+    /// Working directory, relative to the app dir or absolute, `.` for the app dir
+    #[arg(long, value_name = "PATH")]
+    pub cwd: Option<PathBuf>,
+
+    /// Start this app before the app on `run` (repeatable)
+    #[arg(long, value_name = "APP")]
+    #[serde(default)]
+    pub depends_on: Vec<AppName>,
+
+    /// Remove a dependency added with --depends-on (repeatable)
+    #[arg(long, value_name = "APP")]
+    #[serde(default)]
+    pub unset_depends_on: Vec<AppName>,
+
+    /// What run --wait-ready waits for: log:<regex>, cmd:<command>, tcp:[host:]port, http://... or none
+    #[arg(long)]
+    pub ready: Option<String>,
+
+    /// Seconds run --wait-ready waits by default
+    #[arg(long)]
+    pub ready_timeout: Option<u64>,
+}
+
+impl Manager {
+    pub fn new(basedir: impl Into<PathBuf>) -> Self {
+        Manager {
+            basedir: basedir.into(),
+            exe: None,
+            in_process: false,
+        }
+    }
+
+    /// Manager for the state dir the CLI uses (`EXECMGR_HOME`, `XDG_STATE_HOME`, ...)
+    pub fn from_env() -> Self {
+        Manager::new(resolve_base_dir())
+    }
+
+    /// The execmgr binary `run` spawns as supervisor. Without it (or
+    /// `supervise_in_process`), `run` fails: the CLI sets its own executable,
+    /// programs embedding the library point this at an installed execmgr.
+    pub fn with_executable(mut self, exe: impl Into<PathBuf>) -> Self {
+        self.exe = Some(exe.into());
+        self
+    }
+
+    /// Supervise apps started by `run` on a thread of this process, which becomes
+    /// their parent. Only for long-lived processes like the daemon.
+    pub fn supervise_in_process(mut self) -> Self {
+        self.in_process = true;
+        self
+    }
+
+    pub fn basedir(&self) -> &Path {
+        &self.basedir
+    }
+
+    pub fn app_dir(&self, name: &AppName) -> PathBuf {
+        self.basedir.join(name)
+    }
+
+    fn existing_app_dir(&self, name: &AppName) -> Result<PathBuf, Error> {
+        let path = self.app_dir(name);
+        if !path.exists() {
+            return Err(Error::AppNotFound(name.to_string()));
+        }
+        Ok(path)
+    }
+
+    pub fn create(&self, name: &AppName) -> Result<AppStatus, Error> {
+        self.create_app(name, None)
+    }
+
+    /// Creates an app that executes `command` (program and arguments) directly,
+    /// without start.sh or stop.sh
+    pub fn create_command(&self, name: &AppName, command: Vec<String>) -> Result<AppStatus, Error> {
+        self.create_app(name, Some(command))
+    }
+
+    fn create_app(&self, name: &AppName, command: Option<Vec<String>>) -> Result<AppStatus, Error> {
+        let path = self.app_dir(name);
+        if path.exists() {
+            return Err(Error::AppExists(name.to_string()));
+        }
+        let config = AppConfig {
+            command,
+            ..AppConfig::default()
+        };
+        config
+            .validate()
+            .map_err(|invalid| Error::Invalid(invalid.message))?;
+        create_dir_all(&path)
+            .map_err(|e| Error::io(format!("failed to create path {:?}", path), e))?;
+        let files: &[&str] = if config.command.is_some() {
+            &[]
+        } else {
+            &["start.sh", "stop.sh"]
+        };
+        for file in files {
+            let filepath = path.join(file);
+            let mut f = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&filepath)
+                .map_err(|e| Error::io(format!("failed to create file {:?}", filepath), e))?;
+            writeln!(
+                f,
+                "#!/bin/sh\nset -e\nexec echo 'hello from {}'",
+                name.as_str().replace("'", "`")
+            )
+            .map_err(|e| Error::io(format!("failed to create file {:?}", filepath), e))?;
+
+            // chmod +x (755)
+            let mut perms = f
+                .metadata()
+                .map_err(|e| Error::io(format!("failed to get metadata {:?}", filepath), e))?
+                .permissions();
+
+            perms.set_mode(0o755);
+
+            std::fs::set_permissions(&filepath, perms)
+                .map_err(|e| Error::io(format!("failed to set permissions {:?}", filepath), e))?;
+        }
+        let app = App {
+            name: name.to_string(),
+            created_at: Local::now().to_rfc3339(),
+            last_run: None,
+            num_runs: 0,
+            restarts: RestartState::default(),
+            health_state: HealthState::default(),
+        };
+        let filepath = path.join("app.toml");
+        std::fs::write(&filepath, initial_config(config.command.as_deref()))
+            .map_err(|e| Error::io(format!("failed to create file {:?}", filepath), e))?;
+        let app_json = serde_json::to_string_pretty(&app)
+            .map_err(|e| Error::Failed(format!("failed to dump app json: {}", e)))?;
+        let filepath = path.join("app.json");
+        let mut f = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&filepath)
+            .map_err(|e| Error::io(format!("failed to create file {:?}", filepath), e))?;
+        write!(f, "{}", app_json)
+            .map_err(|e| Error::io(format!("failed to create file {:?}", filepath), e))?;
+
+        self.status(name)
+    }
+
+    /// Starts the app under a new supervisor and returns once it is running.
+    /// Dependencies that are down are started first, in order, each waiting
+    /// for its readiness probe if it has one.
+    pub fn run(&self, name: &AppName, origin: Origin, options: RunOptions) -> Result<RunInfo, Error> {
+        let path = self.existing_app_dir(name)?;
+        let config = read_config(&path)?;
+        let script = path.join("start.sh");
+        if config.command.is_none() && !script.exists() {
+            return Err(Error::Invalid(format!(
+                "'{:?}'  not found for '{}'.",
+                script, name
+            )));
+        }
+
+        // a missing working directory, broken .env file or dependency cycle is
+        // reported here rather than by the supervisor
+        work_dir(&path, &config)?;
+        let order = dependency_order(&self.basedir, name, &config)?;
+        env_map(&path, &config)?;
+        let app = read_app(&path)?;
+
+        if check_running(&path)? {
+            return Err(Error::AlreadyRunning {
+                name: name.to_string(),
+                pid: app.last_run.map(|last_run| last_run.pid),
+            });
+        }
+        if !supervisor_done(&path)? {
+            return Err(Error::RestartPending {
+                name: name.to_string(),
+                next_retry: app
+                    .restarts
+                    .next_retry_at
+                    .as_ref()
+                    .map(|t| t.get(..19).unwrap_or(t).replace('T', " ")),
+            });
+        }
+
+        let mut dependencies = Vec::new();
+        for dependency in order.iter().filter(|_| !options.no_deps) {
+            let status = self.status(dependency)?;
+            if status.running || status.pending_restart {
+                continue;
+            }
+            let mut info = self.run(dependency, origin, RunOptions { no_deps: true })?;
+            if status.config.ready.probe.is_some() {
+                info.ready_secs = Some(self.wait_ready(dependency, None)?.as_secs_f64());
+            }
+            dependencies.push(info);
+        }
+
+        // a manual run starts a fresh restart cycle
+        update_app(&path, |app| {
+            app.restarts = RestartState::default();
+            Ok(())
+        })?;
+
+        let line = if self.in_process {
+            self.supervise_on_thread(name, origin)?
+        } else {
+            self.spawn_supervisor(name, origin)?
+        };
+        let line = line.trim();
+        if let Some(e) = line.strip_prefix("error: ") {
+            return Err(handshake_error(e));
+        }
+        let pid: u32 = line
+            .parse()
+            .map_err(|_| Error::Failed(format!("supervisor for '{}' exited unexpectedly", name)))?;
+
+        let app = read_app(&path)?;
+        let started_at = app
+            .last_run
+            .map(|last_run| last_run.time)
+            .unwrap_or_else(|| Local::now().to_rfc3339());
+        Ok(RunInfo {
+            name: name.to_string(),
+            pid,
+            started_at,
+            ready_secs: None,
+            dependencies,
+        })
+    }
+
+    /// Starts `execmgr supervise` and returns its handshake line
+    fn spawn_supervisor(&self, name: &AppName, origin: Origin) -> Result<String, Error> {
+        // the current executable is only execmgr for the CLI
+        let exe = self.exe.as_ref().ok_or_else(|| {
+            Error::Failed(format!(
+                "no execmgr binary to supervise '{}' with, see Manager::with_executable",
+                name
+            ))
+        })?;
+        let mut supervisor = process::Command::new(exe)
+            .arg("supervise")
+            .arg(name.as_str())
+            .arg("--origin")
+            .arg(origin.to_string())
+            .env("EXECMGR_HOME", &self.basedir)
+            .stdin(process::Stdio::null())
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::null())
+            // keep the supervisor (and the app) out of the terminal's process group
+            .process_group(0)
+            .spawn()
+            .map_err(|e| Error::io("failed to spawn supervisor", e))?;
+
+        let mut line = String::new();
+        if let Some(out) = supervisor.stdout.take() {
+            io::BufReader::new(out)
+                .read_line(&mut line)
+                .map_err(|e| Error::io("failed to read from supervisor", e))?;
+        }
+        // reap the supervisor once it's done, in case we outlive it (e.g. `serve`)
+        thread::spawn(move || supervisor.wait());
+        Ok(line)
+    }
+
+    /// Runs the supervisor loop on a new thread and returns its handshake line
+    fn supervise_on_thread(&self, name: &AppName, origin: Origin) -> Result<String, Error> {
+        let (reader, writer) =
+            io::pipe().map_err(|e| Error::io("failed to create supervisor pipe", e))?;
+        let (basedir, name) = (self.basedir.clone(), name.clone());
+        thread::spawn(move || {
+            if let Err(e) = supervise_to(&basedir, &name, origin, writer) {
+                eprintln!("supervisor of '{}' failed: {}", name, e);
+            }
+        });
+        let mut line = String::new();
+        io::BufReader::new(reader)
+            .read_line(&mut line)
+            .map_err(|e| Error::io("failed to read from supervisor", e))?;
+        Ok(line)
+    }
+
+    /// Blocks until the readiness probe of the running app passes and returns
+    /// how long that took. `timeout` defaults to the app's ready timeout.
+    /// Fails with `Error::NotReady` when the app exits or the timeout expires first.
+    pub fn wait_ready(&self, name: &AppName, timeout: Option<u64>) -> Result<Duration, Error> {
+        let path = self.existing_app_dir(name)?;
+        let config = read_config(&path)?;
+        let Some(probe) = &config.ready.probe else {
+            return Err(Error::Invalid(format!(
+                "no readiness probe set for '{}', see `set --ready`",
+                name
+            )));
+        };
+        let timeout = Duration::from_secs(timeout.unwrap_or(config.ready.timeout_secs));
+        let logs = log_paths(&path)?;
+        let pattern = match &probe {
+            ReadyProbe::Log(pattern) => Some(
+                Regex::new(pattern)
+                    .map_err(|e| Error::Invalid(format!("invalid readiness regex: {}", e)))?,
+            ),
+            ReadyProbe::Check(_) => None,
+        };
+        // the logs are read once, after that only the lines they gain
+        let mut followers: Vec<Follower> = Vec::new();
+        let mut lines = Vec::new();
+
+        let started = Instant::now();
+        loop {
+            if !check_running(&path)? {
+                return Err(not_ready(name, &path, exit_reason(&path)));
+            }
+            let Some(left) = timeout.checked_sub(started.elapsed()) else {
+                return Err(not_ready(
+                    name,
+                    &path,
+                    format!("timed out after {}s", timeout.as_secs()),
+                ));
+            };
+            let ready = match &probe {
+                ReadyProbe::Check(check) => {
+                    let timeout = left.clamp(Duration::from_secs(1), Duration::from_secs(5));
+                    health::check(check, 200, timeout, &path, &config).is_ok()
+                }
+                ReadyProbe::Log(_) => {
+                    if followers.is_empty() {
+                        followers = follow_from_start(&logs, &mut lines).unwrap_or_default();
+                    }
+                    for follower in &mut followers {
+                        while follower.read_new_lines(&mut lines).unwrap_or(false) {}
+                    }
+                    // captured lines start with a timestamp, patterns are about the text
+                    let ready = pattern.as_ref().is_some_and(|pattern| {
+                        lines
+                            .iter()
+                            .any(|line| pattern.is_match(strip_timestamp(&line.text)))
+                    });
+                    lines.clear();
+                    ready
+                }
+            };
+            if ready {
+                return Ok(started.elapsed());
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+    }
+
+    /// Body of the hidden `supervise` command, blocks until the app is done for good
+    pub fn supervise(&self, name: &AppName, origin: Origin) -> Result<(), Error> {
+        supervise(&self.basedir, name, origin)
+    }
+
+    pub fn status(&self, name: &AppName) -> Result<AppStatus, Error> {
+        let path = self.existing_app_dir(name)?;
+        let config = read_config(&path)?;
+        status_of(name.to_string(), path, config, None)
+    }
+
+    /// Every app in the state dir, sorted by name. An invalid app.toml is
+    /// reported in `config_error` of that app instead of failing the list.
+    pub fn list(&self) -> Result<Vec<AppStatus>, Error> {
+        self.names()?
+            .into_iter()
+            .map(|name| {
+                let path = self.basedir.join(&name);
+                match read_config(&path) {
+                    Ok(config) => status_of(name, path, config, None),
+                    Err(e) => status_of(name, path, AppConfig::default(), Some(e.to_string())),
+                }
+            })
+            .collect()
+    }
+
+    /// Names of every app in the state dir without reading their metadata, sorted
+    pub fn names(&self) -> Result<Vec<String>, Error> {
+        if !self.basedir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut names = Vec::new();
+        for entry in read_dir(&self.basedir).map_err(|e| Error::io("unable to read dir", e))? {
+            let entry = entry.map_err(|e| Error::io("error while reading entry", e))?;
+            let entry_type = entry
+                .file_type()
+                .map_err(|e| Error::io("error while reading entry type", e))?;
+            if entry_type.is_dir() {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    pub fn info(&self) -> Result<Info, Error> {
+        let mut apps = 0;
+        let mut running = 0;
+        for name in self.names()? {
+            apps += 1;
+            if check_running(&self.basedir.join(name))? {
+                running += 1;
+            }
+        }
+        Ok(Info {
+            basedir: self.basedir.clone(),
+            apps,
+            running,
+            binary: self.exe.clone(),
+            version: env!("CARGO_PKG_VERSION"),
+            daemon: self.in_process,
+        })
+    }
+
+    /// Stops the app: stop.sh, then the stop signal, then SIGKILL. With
+    /// `cascade`, the running apps that depend on it are stopped first.
+    pub fn stop(&self, name: &AppName, options: StopOptions) -> Result<StopResult, Error> {
+        self.stop_with(name, options, |_, _| {})
+    }
+
+    /// Like `stop`, reporting every stage of every app it stops to `progress`
+    pub fn stop_with(
+        &self,
+        name: &AppName,
+        options: StopOptions,
+        mut progress: impl FnMut(&AppName, StopEvent),
+    ) -> Result<StopResult, Error> {
+        // fail before taking the dependents down
+        let path = self.existing_app_dir(name)?;
+        if !options.force && !check_running(&path)? && !check_supervised(&path)? {
+            return Err(Error::NotRunning(name.to_string()));
+        }
+        let running: Vec<AppName> = self
+            .dependents(name)?
+            .into_iter()
+            .filter(|dependent| {
+                self.status(dependent)
+                    .is_ok_and(|status| status.running || status.pending_restart)
+            })
+            .collect();
+        let mut dependents = Vec::new();
+        if options.cascade {
+            let options = StopOptions {
+                force: false,
+                cascade: false,
+                ..options
+            };
+            for dependent in &running {
+                let mut script = None;
+                let stopped = self.stop_app(dependent, options, &mut script, |event| {
+                    progress(dependent, event)
+                })?;
+                dependents.push(StopResult {
+                    name: dependent.to_string(),
+                    stopped,
+                    dependents: Vec::new(),
+                    running_dependents: Vec::new(),
+                    script,
+                });
+            }
+        }
+        let mut script = None;
+        let stopped = self.stop_app(name, options, &mut script, |event| progress(name, event))?;
+        Ok(StopResult {
+            name: name.to_string(),
+            stopped,
+            script,
+            dependents,
+            running_dependents: if options.cascade {
+                Vec::new()
+            } else {
+                running.iter().map(AppName::to_string).collect()
+            },
+        })
+    }
+
+    /// Stops one app, with `capture` the output of stop.sh goes to `script`
+    fn stop_app(
+        &self,
+        name: &AppName,
+        options: StopOptions,
+        script_output: &mut Option<ScriptOutput>,
+        mut progress: impl FnMut(StopEvent),
+    ) -> Result<Stopped, Error> {
+        let path = self.existing_app_dir(name)?;
+        let app = read_app(&path)?;
+        let config = read_config(&path)?;
+        let script = path.join("stop.sh");
+        let use_script = config.stop.use_script && !options.no_script && script.exists();
+
+        if !check_running(&path)? {
+            if options.force {
+                if !script.exists() {
+                    return Err(Error::Invalid(format!(
+                        "'{:?}'  not found for '{}'.",
+                        script, name
+                    )));
+                }
+                let status = run_stop_script(&script, &path, &config, options, script_output)?;
+                if !status.success() {
+                    return Err(Error::Failed(format!("stop.sh failed for app '{}'", name)));
+                }
+                return Ok(Stopped::ForcedScript(status));
+            }
+            if check_supervised(&path)? {
+                request_stop(&path)?;
+                return finish_stop(&path, Stopped::CancelledRestart);
+            }
+            return Err(Error::NotRunning(name.to_string()));
+        }
+
+        request_stop(&path)?;
+        let timeout = Duration::from_secs(options.timeout.unwrap_or(config.stop.timeout_secs));
+
+        if use_script {
+            progress(StopEvent::RunningScript);
+            let status = run_stop_script(&script, &path, &config, options, script_output)?;
+            if !status.success() {
+                progress(StopEvent::ScriptFailed(status));
+            }
+            if wait_stopped(&path, timeout)? {
+                return finish_stop(&path, Stopped::Script);
+            }
+            progress(StopEvent::ScriptTimedOut(timeout));
+        }
+
+        let last_run = app.last_run.as_ref().ok_or_else(|| {
+            Error::Failed(format!(
+                "no pidfile found for '{}'; cannot signal it safely",
+                name
+            ))
+        })?;
+        verify_pid(&path, last_run)?;
+        let pid = last_run.pid;
+        let signal = parse_signal(&config.stop.signal).map_err(Error::Invalid)?;
+        let descendants = options.tree || config.stop.descendants;
+        progress(StopEvent::Signalling { signal, pid });
+        signal_app(&path, pid, signal, descendants)?;
+        if wait_stopped(&path, timeout)? {
+            return finish_stop(&path, Stopped::Signal(signal));
+        }
+
+        progress(StopEvent::Escalating(timeout));
+        signal_app(&path, pid, libc::SIGKILL, descendants)?;
+        if wait_stopped(&path, Duration::from_secs(5))? {
+            return finish_stop(&path, Stopped::Kill);
+        }
+        if descendants {
+            return Err(Error::Failed(format!(
+                "app '{}' is still running after SIGKILL",
+                name
+            )));
+        }
+        Err(Error::Failed(format!(
+            "app '{}' is still running after SIGKILL, a process that left its group may hold the lock (retry with --tree)",
+            name
+        )))
+    }
+
+    /// Sends SIGKILL to the app's process group
+    pub fn kill(&self, name: &AppName, tree: bool) -> Result<KillResult, Error> {
+        let path = self.existing_app_dir(name)?;
+        let config = read_config(&path)?;
+        let result = |killed| KillResult {
+            name: name.to_string(),
+            killed,
+        };
+        if !check_running(&path)? {
+            if check_supervised(&path)? {
+                request_stop(&path)?;
+                wait_unsupervised(&path, SUPERVISOR_EXIT_TIMEOUT)?;
+                return Ok(result(Killed::CancelledRestart));
+            }
+            return Err(Error::NotRunning(name.to_string()));
+        }
+
+        let app = read_app(&path)?;
+
+        let last_run = if let Some(last_run) = &app.last_run {
+            last_run
+        } else {
+            return Err(Error::Failed(format!(
+                "no pidfile found for '{}'; cannot force kill safely",
+                name
+            )));
+        };
+
+        verify_pid(&path, last_run)?;
+        request_stop(&path)?;
+        signal_app(&path, last_run.pid, libc::SIGKILL, tree || config.stop.descendants)?;
+        // the lock may outlive the group in a process that left it, `kill` doesn't insist
+        if wait_stopped(&path, Duration::from_secs(5))? {
+            wait_unsupervised(&path, SUPERVISOR_EXIT_TIMEOUT)?;
+        }
+
+        Ok(result(Killed::Pid(last_run.pid)))
+    }
+
+    /// Recorded runs, oldest first, including the current one. `limit` keeps the most recent.
+    pub fn history(&self, name: &AppName, limit: Option<usize>) -> Result<Vec<RunRecord>, Error> {
+        let path = self.existing_app_dir(name)?;
+        let mut runs = read_history(&path)?;
+
+        // the current run only lands in history.jsonl once it ends
+        let app = read_app(&path)?;
+        if let Some(last_run) = &app.last_run
+            && last_run.ended_at.is_none()
+            && check_running(&path)?
+        {
+            runs.push(RunRecord::new(app.num_runs, last_run));
+        }
+
+        if let Some(limit) = limit {
+            runs.drain(..runs.len().saturating_sub(limit));
+        }
+        Ok(runs)
+    }
+
+    /// Settings of the app from app.toml
+    pub fn config(&self, name: &AppName) -> Result<AppConfig, Error> {
+        read_config(&self.existing_app_dir(name)?)
+    }
+
+    /// Apps the app depends on, transitively and in the order `run` starts them
+    pub fn dependencies(&self, name: &AppName) -> Result<Vec<AppName>, Error> {
+        let config = self.config(name)?;
+        dependency_order(&self.basedir, name, &config)
+    }
+
+    /// Apps that depend on the app, transitively and in the order they have to
+    /// be stopped: every app comes before the ones it depends on. Apps with a
+    /// broken app.toml are left out.
+    pub fn dependents(&self, name: &AppName) -> Result<Vec<AppName>, Error> {
+        self.existing_app_dir(name)?;
+        let mut apps: Vec<(AppName, Vec<AppName>)> = Vec::new();
+        for other in self.names()? {
+            let (Ok(other), Ok(config)) = (
+                other.parse::<AppName>(),
+                read_config(&self.basedir.join(&other)),
+            ) else {
+                continue;
+            };
+            apps.push((other, config.depends_on));
+        }
+
+        let mut dependents = Vec::new();
+        let mut queue = vec![name.clone()];
+        while let Some(current) = queue.pop() {
+            for (other, depends_on) in &apps {
+                if depends_on.contains(&current) && !dependents.contains(other) && other != name {
+                    dependents.push(other.clone());
+                    queue.push(other.clone());
+                }
+            }
+        }
+
+        // take an app once no remaining one depends on it, in a cycle any of them
+        let mut order = Vec::with_capacity(dependents.len());
+        while !dependents.is_empty() {
+            let next = dependents
+                .iter()
+                .position(|candidate| {
+                    !apps.iter().any(|(other, depends_on)| {
+                        dependents.contains(other) && depends_on.contains(candidate)
+                    })
+                })
+                .unwrap_or(0);
+            order.push(dependents.remove(next));
+        }
+        Ok(order)
+    }
+
+    /// Environment the app is started with, sorted by name. Values of secrets
+    /// are replaced by `********` unless `reveal` is set.
+    pub fn env(&self, name: &AppName, reveal: bool) -> Result<Vec<EnvVar>, Error> {
+        let path = self.existing_app_dir(name)?;
+        let mut vars = resolve_env(&path, &read_config(&path)?)?;
+        if !reveal {
+            for var in vars.iter_mut().filter(|var| var.secret) {
+                var.value = "********".into();
+            }
+        }
+        Ok(vars)
+    }
+
+    /// Applies `settings` to app.toml and returns the updated config
+    pub fn set(&self, name: &AppName, settings: &Settings) -> Result<AppConfig, Error> {
+        let path = self.existing_app_dir(name)?;
+        let mut config = read_config(&path)?;
+        let policy = &mut config.restart;
+        if let Some(mode) = settings.restart {
+            policy.mode = mode;
+        }
+        if let Some(max_retries) = settings.max_retries {
+            policy.max_retries = max_retries;
+        }
+        if let Some(backoff) = settings.backoff {
+            policy.backoff_secs = backoff;
+        }
+        if let Some(max_backoff) = settings.max_backoff {
+            policy.max_backoff_secs = max_backoff;
+        }
+        if let Some(reset_after) = settings.reset_after {
+            policy.reset_after_secs = reset_after;
+        }
+        if let Some(keep_runs) = settings.keep_runs {
+            config.logs.keep_runs = keep_runs;
+        }
+        if let Some(max_size) = settings.max_log_size {
+            config.logs.max_size = max_size;
+        }
+        if let Some(max_files) = settings.max_log_files {
+            config.logs.max_files = max_files;
+        }
+        if let Some(compress) = settings.compress_logs {
+            config.logs.compress = compress;
+        }
+        if let Some(timestamps) = settings.timestamps {
+            config.logs.timestamps = timestamps;
+        }
+        if let Some(use_script) = settings.stop_script {
+            config.stop.use_script = use_script;
+        }
+        if let Some(signal) = &settings.stop_signal {
+            config.stop.signal = signal_name(parse_signal(signal).map_err(Error::Invalid)?);
+        }
+        if let Some(timeout) = settings.stop_timeout {
+            config.stop.timeout_secs = timeout;
+        }
+        if let Some(descendants) = settings.kill_descendants {
+            config.stop.descendants = descendants;
+        }
+        if let Some(probe) = &settings.health {
+            config.health.probe = match probe.as_str() {
+                "none" => None,
+                probe => Some(probe.parse::<HealthProbe>().map_err(Error::Invalid)?),
+            };
+        }
+        if let Some(status) = settings.health_status {
+            config.health.status = status;
+        }
+        if let Some(interval) = settings.health_interval {
+            config.health.interval_secs = interval;
+        }
+        if let Some(timeout) = settings.health_timeout {
+            config.health.timeout_secs = timeout;
+        }
+        if let Some(threshold) = settings.health_threshold {
+            config.health.threshold = threshold;
+        }
+        if let Some(restart) = settings.health_restart {
+            config.health.restart = restart;
+        }
+        if let Some(probe) = &settings.ready {
+            config.ready.probe = match probe.as_str() {
+                "none" => None,
+                probe => Some(probe.parse::<ReadyProbe>().map_err(Error::Invalid)?),
+            };
+        }
+        if let Some(timeout) = settings.ready_timeout {
+            config.ready.timeout_secs = timeout;
+        }
+        for var in &settings.env {
+            let Some((key, value)) = var.split_once('=') else {
+                return Err(Error::Invalid(format!("expected KEY=VALUE, found {:?}", var)));
+            };
+            config.env.insert(key.to_string(), value.to_string());
+        }
+        for key in &settings.unset_env {
+            config.env.remove(key);
+        }
+        for file in &settings.env_file {
+            if !config.env_files.contains(file) {
+                config.env_files.push(file.clone());
+            }
+        }
+        if let Some(clean_env) = settings.clean_env {
+            config.clean_env = clean_env;
+        }
+        if let Some(cwd) = &settings.cwd {
+            config.cwd = (cwd != Path::new(".")).then(|| cwd.clone());
+        }
+        for dependency in &settings.depends_on {
+            if !config.depends_on.contains(dependency) {
+                config.depends_on.push(dependency.clone());
+            }
+        }
+        config
+            .depends_on
+            .retain(|dependency| !settings.unset_depends_on.contains(dependency));
+        config
+            .validate()
+            .map_err(|invalid| Error::Invalid(invalid.message))?;
+        // our app.toml doesn't have the new dependencies yet, there is no line to point at
+        dependency_order(&self.basedir, name, &config).map_err(|e| match e {
+            Error::Config { path: file, message, .. } if file == path.join(CONFIG_FILE) => {
+                Error::Invalid(message)
+            }
+            e => e,
+        })?;
+        write_config(&path, &config)?;
+        Ok(config)
+    }
+
+    /// Removes the app folder, refuses while the app is running or about to
+    /// restart, and while other apps depend on it unless `force` is set
+    pub fn delete(&self, name: &AppName, force: bool) -> Result<DeleteResult, Error> {
+        let path = self.existing_app_dir(name)?;
+        ensure_stopped(name, &path)?;
+        let dependents = self.dependents(name)?;
+        if !force && !dependents.is_empty() {
+            let names: Vec<&str> = dependents.iter().map(AppName::as_str).collect();
+            return Err(Error::Invalid(format!(
+                "'{}' is a dependency of {}, remove it from their depends_on or force the delete",
+                name,
+                names.join(", ")
+            )));
+        }
+        remove_dir_all(&path).map_err(|e| Error::io(format!("unable to delete {:?}", path), e))?;
+        Ok(DeleteResult {
+            name: name.to_string(),
+            deleted: true,
+        })
+    }
+
+    /// Moves the app folder to `new_name`, refuses while the app is running or about to restart
+    pub fn rename(&self, name: &AppName, new_name: &AppName) -> Result<AppStatus, Error> {
+        let path = self.existing_app_dir(name)?;
+        let new_path = self.app_dir(new_name);
+        if new_path.exists() {
+            return Err(Error::AppExists(new_name.to_string()));
+        }
+        ensure_stopped(name, &path)?;
+        std::fs::rename(&path, &new_path)
+            .map_err(|e| Error::io(format!("unable to rename {:?}", path), e))?;
+        update_app(&new_path, |app| {
+            app.name = new_name.to_string();
+            Ok(())
+        })?;
+        self.status(new_name)
+    }
+
+    /// Contents of start.sh and stop.sh, empty when missing
+    pub fn scripts(&self, name: &AppName) -> Result<Scripts, Error> {
+        let path = self.existing_app_dir(name)?;
+        let read = |file: &str| {
+            let script = path.join(file);
+            match std::fs::read_to_string(&script) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+                result => result.map_err(|e| Error::io(format!("failed to read {:?}", script), e)),
+            }
+        };
+        Ok(Scripts {
+            start: read("start.sh")?,
+            stop: read("stop.sh")?,
+        })
+    }
+
+    /// Replaces start.sh and stop.sh, both end up executable
+    pub fn save_scripts(&self, name: &AppName, scripts: &Scripts) -> Result<(), Error> {
+        let path = self.existing_app_dir(name)?;
+        for (file, content) in [("start.sh", &scripts.start), ("stop.sh", &scripts.stop)] {
+            let script = path.join(file);
+            std::fs::write(&script, content)
+                .map_err(|e| Error::io(format!("failed to write {:?}", script), e))?;
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))
+                .map_err(|e| Error::io(format!("failed to set permissions {:?}", script), e))?;
+        }
+        Ok(())
+    }
+
+    /// Empties the current logs and removes their rotated segments
+    pub fn clear_logs(
+        &self,
+        name: &AppName,
+        stdout: bool,
+        stderr: bool,
+    ) -> Result<ClearResult, Error> {
+        let path = self.existing_app_dir(name)?;
+        let logs = log_paths(&path)?;
+        let mut cleared = Vec::new();
+        for (log, stream, clear) in [
+            (&logs.stdout, Stream::Stdout, stdout),
+            (&logs.stderr, Stream::Stderr, stderr),
+        ] {
+            if !clear {
+                continue;
+            }
+            if log.exists() {
+                std::fs::write(log, "")
+                    .map_err(|e| Error::io(format!("failed to clear {:?}", log), e))?;
+            }
+            remove_segments(log)?;
+            cleared.push(stream);
+        }
+        Ok(ClearResult {
+            name: name.to_string(),
+            cleared,
+        })
+    }
+
+    /// Lines currently in the logs, including rotated segments
+    pub fn logs(&self, name: &AppName, query: &LogQuery) -> Result<Vec<LogLine>, Error> {
+        let (_, logs) = self.resolve_logs(name, query)?;
+        let (show_stdout, show_stderr) = query.streams();
+        let mut lines = Vec::new();
+        for (log, stream, show) in [
+            (&logs.stdout, Stream::Stdout, show_stdout),
+            (&logs.stderr, Stream::Stderr, show_stderr),
+        ] {
+            if !show {
+                continue;
+            }
+            let content = read_log(log)?;
+            let mut last_time = None;
+            for line in filter_lines(&content, query.since, query.until) {
+                if let Some(time) = line_time(line) {
+                    last_time = Some(time);
+                }
+                lines.push(LogLine {
+                    stream,
+                    time: last_time,
+                    text: line.to_string(),
+                });
+            }
+        }
+        if query.merged {
+            // stable, so lines of one stream keep their order
+            lines.sort_by_key(|line| line.time);
+        }
+        Ok(lines)
+    }
+
+    /// Follows the current logs: hands the last `tail` lines of each stream (or every
+    /// line since `query.since`) and then new lines to `on_lines` in batches, until
+    /// it returns false or, with `exit_on_stopped`, the app stops. `on_lines` is
+    /// called on every poll, with an empty batch when nothing was logged.
+    pub fn follow_logs(
+        &self,
+        name: &AppName,
+        query: &LogQuery,
+        tail: usize,
+        exit_on_stopped: bool,
+        mut on_lines: impl FnMut(Vec<LogLine>) -> bool,
+    ) -> Result<(), Error> {
+        let query = LogQuery {
+            run: None,
+            until: None,
+            ..query.clone()
+        };
+        let (path, logs) = self.resolve_logs(name, &query)?;
+        let (show_stdout, show_stderr) = query.streams();
+
+        let mut followers = Vec::new();
+        let mut batch = Vec::new();
+        if show_stdout && logs.stdout.exists() {
+            followers.push(
+                Follower::new(&logs.stdout, Stream::Stdout, tail, query.since, &mut batch)
+                    .map_err(|e| Error::io("failed to follow stdout", e))?,
+            );
+        }
+        if show_stderr && logs.stderr.exists() {
+            followers.push(
+                Follower::new(&logs.stderr, Stream::Stderr, tail, query.since, &mut batch)
+                    .map_err(|e| Error::io("failed to follow stderr", e))?,
+            );
+        }
+        if followers.is_empty() {
+            return Err(Error::Failed("no log files could be opened".into()));
+        }
+
+        let mut has_seen_running = false;
+        let mut startup_checks = 10;
+
+        loop {
+            let mut read_anything = false;
+            for follower in &mut followers {
+                if follower
+                    .read_new_lines(&mut batch)
+                    .map_err(|e| Error::io("error reading logs", e))?
+                {
+                    read_anything = true;
+                }
+            }
+            if query.merged {
+                // stable, so lines of one stream keep their order
+                batch.sort_by_key(|line: &LogLine| line.time);
+            }
+            if !on_lines(std::mem::take(&mut batch)) {
+                return Ok(());
+            }
+            if !read_anything {
+                if exit_on_stopped {
+                    if check_running(&path)? {
+                        has_seen_running = true;
+                    } else if has_seen_running {
+                        break;
+                    } else if startup_checks > 0 {
+                        startup_checks -= 1;
+                    } else {
+                        break;
+                    }
+                }
+                thread::sleep(Duration::from_millis(100));
+            } else if exit_on_stopped {
+                has_seen_running = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_logs(
+        &self,
+        name: &AppName,
+        query: &LogQuery,
+    ) -> Result<(PathBuf, LogPath), Error> {
+        let path = self.existing_app_dir(name)?;
+        let app = read_app(&path)?;
+        let config = read_config(&path)?;
+        if (query.since.is_some() || query.until.is_some() || query.merged)
+            && !config.logs.timestamps
+        {
+            return Err(Error::Invalid(format!(
+                "logs of '{}' have no timestamps, enable them with `execmgr set {} --timestamps true`",
+                name, name
+            )));
+        }
+        let logs = match query.run {
+            Some(run) if run != app.num_runs => {
+                let logs = run_log_paths(&path, run);
+                if !logs.stdout.exists() && !logs.stderr.exists() {
+                    return Err(Error::Invalid(format!(
+                        "no logs kept for run {} of '{}'",
+                        run, name
+                    )));
+                }
+                logs
+            }
+            _ => log_paths(&path)?,
+        };
+        if !logs.stdout.exists() && !logs.stderr.exists() {
+            return Err(Error::Failed(
+                "no logs found (app may not have been run yet)".into(),
+            ));
+        }
+        Ok((path, logs))
+    }
+}
+
+/// Runs stop.sh attached, or captured into `output` with `capture`
+fn run_stop_script(
+    script: &Path,
+    path: &Path,
+    config: &AppConfig,
+    options: StopOptions,
+    output: &mut Option<ScriptOutput>,
+) -> Result<ExitStatus, Error> {
+    if !options.capture {
+        return run_attached(script, path, config);
+    }
+    let captured = run_captured(script, path, config)?;
+    *output = Some(ScriptOutput {
+        exit_code: captured.status.code(),
+        stdout: String::from_utf8_lossy(&captured.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&captured.stderr).into_owned(),
+    });
+    Ok(captured.status)
+}
+
+fn status_of(
+    name: String,
+    path: PathBuf,
+    config: AppConfig,
+    config_error: Option<String>,
+) -> Result<AppStatus, Error> {
+    let app = read_app(&path)?;
+    let running = check_running(&path)?;
+    let pending_restart = !running && check_supervised(&path)?;
+    let last_run = app.last_run.as_ref().filter(|_| running);
+    let health = (running && config.health.probe.is_some()).then_some(app.health_state.status);
+    Ok(AppStatus {
+        name,
+        path,
+        running,
+        pending_restart,
+        pid: last_run.map(|last_run| last_run.pid),
+        uptime_secs: last_run.and_then(|last_run| since_running(&last_run.time)),
+        health,
+        app,
+        config,
+        config_error,
+    })
+}
+
+/// The error behind `<exit code> <message>` of a failed handshake, keeping
+/// the supervisor's message and exit code as they were
+fn handshake_error(error: &str) -> Error {
+    match error
+        .split_once(' ')
+        .and_then(|(code, message)| Some((code.parse().ok()?, message)))
+    {
+        Some((code, message)) => Error::Remote {
+            message: message.to_string(),
+            code,
+        },
+        None => Error::Failed(error.to_string()),
+    }
+}
+
+/// How a run that ended before it was ready went, once the supervisor recorded it
+fn exit_reason(path: &Path) -> String {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    loop {
+        let exit = read_app(path)
+            .ok()
+            .and_then(|app| app.last_run)
+            .filter(|last_run| last_run.ended_at.is_some())
+            .and_then(|last_run| last_run.exit_status());
+        match exit {
+            Some(exit) => return format!("exited before it was ready (exit: {})", exit),
+            None if Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+            None => return "exited before it was ready".into(),
+        }
+    }
+}
+
+/// Followers of both logs, the lines logged so far (rotated segments included)
+/// go to `lines`. A run starts with neither logs nor segments of the previous
+/// run, so these are all of the current run.
+fn follow_from_start(logs: &LogPath, lines: &mut Vec<LogLine>) -> Result<Vec<Follower>, Error> {
+    let mut followers = Vec::new();
+    for (log, stream) in [(&logs.stdout, Stream::Stdout), (&logs.stderr, Stream::Stderr)] {
+        for text in read_segments(log)?.lines() {
+            lines.push(LogLine {
+                stream,
+                time: None,
+                text: text.to_string(),
+            });
+        }
+        let follower = Follower::new(log, stream, usize::MAX, None, lines)
+            .map_err(|e| Error::io(format!("failed to open {:?}", log), e))?;
+        followers.push(follower);
+    }
+    Ok(followers)
+}
+
+/// `Error::NotReady` with the last lines of stderr.log
+fn not_ready(name: &AppName, path: &Path, reason: String) -> Error {
+    let stderr = log_paths(path)
+        .and_then(|logs| read_log(&logs.stderr))
+        .unwrap_or_default();
+    let lines: Vec<&str> = stderr.lines().collect();
+    Error::NotReady {
+        name: name.to_string(),
+        reason,
+        stderr: lines[lines.len().saturating_sub(STDERR_TAIL)..].join("\n"),
+    }
+}
+
+fn ensure_stopped(name: &AppName, path: &Path) -> Result<(), Error> {
+    if check_running(path)? {
+        return Err(Error::AlreadyRunning {
+            name: name.to_string(),
+            pid: None,
+        });
+    }
+    if !supervisor_done(path)? {
+        return Err(Error::RestartPending {
+            name: name.to_string(),
+            next_retry: None,
+        });
+    }
+    Ok(())
+}
+
+/// Whether no supervisor is left. One whose app is down without a restart
+/// scheduled is only recording the last exit, that is waited for.
+fn supervisor_done(path: &Path) -> Result<bool, Error> {
+    let deadline = Instant::now() + SUPERVISOR_EXIT_TIMEOUT;
+    while check_supervised(path)? {
+        // restarting, or about to
+        if check_running(path)?
+            || read_app(path)?.restarts.next_retry_at.is_some()
+            || Instant::now() >= deadline
+        {
+            return Ok(false);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    Ok(true)
+}
+
+/// Returns `stopped` once the supervisor, which may still be recording the
+/// exit, is gone too, so a `run` right after doesn't find it
+fn finish_stop(path: &Path, stopped: Stopped) -> Result<Stopped, Error> {
+    wait_unsupervised(path, SUPERVISOR_EXIT_TIMEOUT)?;
+    Ok(stopped)
+}
+
+/// Tells the supervisor not to bring the app back once it goes down
+fn request_stop(path: &Path) -> Result<(), Error> {
+    update_app(path, |app| {
+        app.restarts.stop_requested = true;
+        Ok(())
+    })
+}
+
+struct Follower {
+    path: PathBuf,
+    file: File,
+    ino: u64,
+    buffer: Vec<u8>,
+    stream: Stream,
+    last_time: Option<DateTime<Local>>,
+}
+
+impl Follower {
+    fn new(
+        path: &Path,
+        stream: Stream,
+        tail: usize,
+        since: Option<DateTime<Local>>,
+        batch: &mut Vec<LogLine>,
+    ) -> Result<Self, io::Error> {
+        let mut file = File::open(path)?;
+        let ino = file.metadata()?.ino();
+        // following continues right after what is read here, not at the end
+        // the file has by then
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+        let content = String::from_utf8_lossy(&content);
+        let lines: Vec<&str> = match since {
+            Some(since) => filter_lines(&content, Some(since), None),
+            None => content.lines().collect(),
+        };
+        let start_idx = if since.is_some() {
+            0
+        } else {
+            lines.len().saturating_sub(tail)
+        };
+        let mut follower = Follower {
+            path: path.to_path_buf(),
+            file,
+            ino,
+            buffer: Vec::new(),
+            stream,
+            last_time: None,
+        };
+        for line in &lines[start_idx..] {
+            follower.push_line(line, batch);
+        }
+        Ok(follower)
+    }
+
+    fn push_line(&mut self, line: &str, batch: &mut Vec<LogLine>) {
+        if let Some(time) = line_time(line) {
+            self.last_time = Some(time);
+        }
+        batch.push(LogLine {
+            stream: self.stream,
+            time: self.last_time,
+            text: line.to_string(),
+        });
+    }
+
+    fn read_new_lines(&mut self, batch: &mut Vec<LogLine>) -> Result<bool, io::Error> {
+        let mut temp_buf = [0u8; 4096];
+        let bytes_read = self.file.read(&mut temp_buf)?;
+        if bytes_read == 0 {
+            // the log was rotated or truncated by a new run, start over on the new file
+            let position = self.file.stream_position()?;
+            return match std::fs::metadata(&self.path) {
+                Ok(meta) if meta.ino() != self.ino || meta.len() < position => {
+                    self.file = File::open(&self.path)?;
+                    self.ino = meta.ino();
+                    Ok(true)
+                }
+                _ => Ok(false),
+            };
+        }
+
+        self.buffer.extend_from_slice(&temp_buf[..bytes_read]);
+        let mut start = 0;
+        for i in 0..self.buffer.len() {
+            if self.buffer[i] == b'\n' {
+                let line = String::from_utf8_lossy(&self.buffer[start..i]).into_owned();
+                self.push_line(&line, batch);
+                start = i + 1;
+            }
+        }
+        if start > 0 {
+            self.buffer.drain(0..start);
+        }
+        Ok(true)
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// The fields of `/proc/<pid>/stat` execmgr cares about
+#[derive(Debug, Clone)]
+pub struct ProcStat {
+    pub pid: u32,
+    pub ppid: u32,
+    pub pgrp: u32,
+    pub session: u32,
+    /// clock ticks after boot the process started at, stays the same across exec
+    pub starttime: u64,
+}
+
+/// Parses `/proc/<pid>/stat`, `None` if the process is gone
+pub fn read_stat(pid: u32) -> Option<ProcStat> {
+    let content = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    parse_stat(&content)
+}
+
+fn parse_stat(content: &str) -> Option<ProcStat> {
+    // the command name is in parentheses and may itself contain spaces or ')'
+    let (pid, rest) = content.split_once(" (")?;
+    let (_, fields) = rest.rsplit_once(") ")?;
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    Some(ProcStat {
+        pid: pid.trim().parse().ok()?,
+        ppid: fields.get(1)?.parse().ok()?,
+        pgrp: fields.get(2)?.parse().ok()?,
+        session: fields.get(3)?.parse().ok()?,
+        starttime: fields.get(19)?.parse().ok()?,
+    })
+}
+
+/// Start time of `pid` in clock ticks after boot, `None` if the process is gone
+pub fn start_time(pid: u32) -> Option<u64> {
+    read_stat(pid).map(|stat| stat.starttime)
+}
+
+/// Pids that hold a lock on `path` according to /proc/locks
+pub fn lock_holders(path: &Path) -> Vec<u32> {
+    let (Ok(meta), Ok(locks)) = (fs::metadata(path), fs::read_to_string("/proc/locks")) else {
+        return Vec::new();
+    };
+    let dev = meta.dev();
+    // the kernel prints the device as hex major:minor followed by the decimal inode
+    let id = format!(
+        "{:02x}:{:02x}:{}",
+        libc::major(dev),
+        libc::minor(dev),
+        meta.ino()
+    );
+    locks
+        .lines()
+        .filter_map(|line| {
+            // "1: FLOCK  ADVISORY  WRITE 1234 08:02:131 0 EOF", blocked waiters have "->"
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.get(1) == Some(&"->") || fields.get(5) != Some(&id.as_str()) {
+                return None;
+            }
+            fields.get(4)?.parse().ok()
+        })
+        .collect()
+}
+
+/// Every process currently listed in /proc
+pub fn all_processes() -> Vec<ProcStat> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .filter_map(read_stat)
+        .collect()
+}
+
+/// Processes that have `path` open, e.g. everything that inherited the app lock
+pub fn processes_with_open(path: &Path) -> Vec<u32> {
+    let Ok(target) = fs::canonicalize(path) else {
+        return Vec::new();
+    };
+    let own = std::process::id();
+    all_processes()
+        .into_iter()
+        .map(|process| process.pid)
+        .filter(|pid| *pid != own)
+        .filter(|pid| {
+            let Ok(fds) = fs::read_dir(format!("/proc/{}/fd", pid)) else {
+                return false;
+            };
+            fds.flatten()
+                .any(|fd| fs::read_link(fd.path()).is_ok_and(|link| link == target))
+        })
+        .collect()
+}
+
+/// Processes started by `pid`, directly or not, that moved to another process
+/// group or session. Orphans that were reparented after their parent died are
+/// found through the session of `pid` or because they still hold `lock` open.
+pub fn escaped_descendants(pid: u32, lock: &Path) -> Vec<u32> {
+    let processes = all_processes();
+    let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+    for process in &processes {
+        children.entry(process.ppid).or_default().push(process.pid);
+    }
+
+    let mut found: Vec<u32> = processes
+        .iter()
+        .filter(|process| process.session == pid && process.pid != pid)
+        .map(|process| process.pid)
+        .collect();
+    for holder in processes_with_open(lock) {
+        if holder != pid && !found.contains(&holder) {
+            found.push(holder);
+        }
+    }
+    let mut queue = vec![pid];
+    queue.extend(found.iter().copied());
+    while let Some(parent) = queue.pop() {
+        for child in children.get(&parent).into_iter().flatten() {
+            if *child != pid && !found.contains(child) {
+                found.push(*child);
+                queue.push(*child);
+            }
+        }
+    }
+    // the rest is reached by signalling the group
+    let pgrps: HashMap<u32, u32> = processes.iter().map(|p| (p.pid, p.pgrp)).collect();
+    found.retain(|child| pgrps.get(child).is_some_and(|pgrp| *pgrp != pid));
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_stat_fields() {
+        let stat = parse_stat(
+            "4242 (web) S 1 4240 4200 34816 4240 4194560 120 0 0 0 5 3 0 0 20 0 1 0 987654 \
+             4096 100 18446744073709551615 0 0 0 0 0 0 0 0 0 0 0 0 17 2 0 0 0 0 0\n",
+        )
+        .unwrap();
+        assert_eq!(
+            (stat.pid, stat.ppid, stat.pgrp, stat.session, stat.starttime),
+            (4242, 1, 4240, 4200, 987654)
+        );
+    }
+
+    #[test]
+    fn parse_stat_command_with_spaces_and_parentheses() {
+        let stat = parse_stat("17 (a (b) c) ) R 2 17 17 0 -1 0 0 0 0 0 0 0 0 0 20 0 1 0 555 0 0\n")
+            .unwrap();
+        assert_eq!((stat.pid, stat.ppid, stat.starttime), (17, 2, 555));
+    }
+
+    #[test]
+    fn parse_stat_rejects_truncated_lines() {
+        assert!(parse_stat("").is_none());
+        assert!(parse_stat("17 (sh) S 1 17").is_none());
+        assert!(parse_stat("x (sh) S 1 17 17 0 -1 0 0 0 0 0 0 0 0 0 20 0 1 0 555").is_none());
+    }
+
+    #[test]
+    fn read_stat_of_this_process() {
+        let stat = read_stat(std::process::id()).unwrap();
+        assert_eq!(stat.pid, std::process::id());
+        assert_eq!(start_time(stat.pid), Some(stat.starttime));
+    }
+}
@@ -0,0 +1,619 @@
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{Value, json};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use execmgr::{
+    AppName, AppStatus, Error, Killed, LogQuery, Manager, Origin, RunOptions, Scripts, StopOptions,
+};
+
+/// Lines of a log sent before following it, like the Python API did
+const STREAM_TAIL: usize = 500;
+const EVENTS_INTERVAL: Duration = Duration::from_secs(2);
+/// SSE comment sent on quiet streams, so a closed client gets noticed
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Entry point of `serve`: the HTTP API of the web UI (the routes of the old
+/// FastAPI service), one thread per request. With `static_dir`, unknown GET
+/// paths are served from the built frontend. Browsers may only call it from
+/// the address it is served on and from `allow_origins`, and only under the
+/// bind address, localhost or 127.0.0.1 (no DNS rebinding).
+pub fn serve(
+    manager: &Manager,
+    bind: &str,
+    static_dir: Option<PathBuf>,
+    allow_origins: Vec<String>,
+) -> Result<(), Error> {
+    let server = Server::http(bind)
+        .map_err(|e| Error::Failed(format!("failed to listen on {}: {}", bind, e)))?;
+    println!("listening on http://{}", bind);
+    let mut origins = allow_origins;
+    origins.push(format!("http://{}", bind));
+    let access = Arc::new(Access {
+        origins,
+        hosts: allowed_hosts(bind),
+    });
+    for request in server.incoming_requests() {
+        let manager = manager.clone();
+        let static_dir = static_dir.clone();
+        let access = access.clone();
+        thread::spawn(move || handle(&manager, request, static_dir.as_deref(), &access));
+    }
+    Ok(())
+}
+
+/// Who may call the API: the `Origin`s browsers may call it from and the
+/// `Host`s it answers to
+struct Access {
+    origins: Vec<String>,
+    hosts: Vec<String>,
+}
+
+/// The bind address, and localhost and 127.0.0.1 with its port
+fn allowed_hosts(bind: &str) -> Vec<String> {
+    let mut hosts = vec![bind.to_string()];
+    if let Some((_, port)) = bind.rsplit_once(':') {
+        hosts.push(format!("localhost:{}", port));
+        hosts.push(format!("127.0.0.1:{}", port));
+    }
+    hosts
+}
+
+/// A finished response, SSE streams write to the connection themselves
+struct Reply {
+    status: u16,
+    body: Value,
+}
+
+impl Reply {
+    fn ok(body: Value) -> Self {
+        Reply { status: 200, body }
+    }
+
+    fn message(message: String) -> Self {
+        Reply::ok(json!({ "message": message }))
+    }
+
+    fn error(status: u16, detail: impl Into<String>) -> Self {
+        Reply {
+            status,
+            body: json!({ "detail": detail.into() }),
+        }
+    }
+}
+
+impl From<Error> for Reply {
+    fn from(e: Error) -> Self {
+        let status = match e {
+            Error::AppNotFound(_) => 404,
+            Error::Failed(_) | Error::Io { .. } | Error::CorruptMetadata { .. } => 500,
+            _ => 400,
+        };
+        Reply::error(status, e.to_string())
+    }
+}
+
+fn handle(manager: &Manager, mut request: Request, static_dir: Option<&Path>, access: &Access) {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let segments = url_segments(path);
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let method = request.method().clone();
+
+    // a page of another site may resolve its own name to this address
+    if let Some(host) = header_value(&request, "Host")
+        && !access
+            .hosts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(&host))
+    {
+        let reply = Reply::error(403, format!("host '{}' is not allowed", host));
+        respond(request, reply, None);
+        return;
+    }
+
+    // only browsers send an Origin, a page of another site gets no CORS
+    // headers and can't change anything
+    let cors = match header_value(&request, "Origin") {
+        Some(origin) if access.origins.contains(&origin) => Some(origin),
+        Some(origin) if method != Method::Get => {
+            let reply = Reply::error(403, format!("origin '{}' is not allowed", origin));
+            respond(request, reply, None);
+            return;
+        }
+        _ => None,
+    };
+    let cors = cors.as_deref();
+
+    if method == Method::Options {
+        request
+            .respond(
+                with_cors(Response::empty(204), cors)
+                    .with_header(header(
+                        "Access-Control-Allow-Methods",
+                        "GET, POST, PUT, DELETE",
+                    ))
+                    .with_header(header("Access-Control-Allow-Headers", "Content-Type")),
+            )
+            .ok();
+        return;
+    }
+
+    match (&method, segments.as_slice()) {
+        (Method::Get, ["events"]) => {
+            stream_events(manager, request.into_writer(), cors);
+            return;
+        }
+        (Method::Get, ["apps", name, "logs", "stream"]) => {
+            let stream = query_param(query, "type").unwrap_or_else(|| "stdout".into());
+            match (parse_name(name), stream.as_str()) {
+                (Err(reply), _) => respond(request, reply, cors),
+                (Ok(_), stream) if stream != "stdout" && stream != "stderr" => respond(
+                    request,
+                    Reply::error(400, "Invalid log type. Must be 'stdout' or 'stderr'."),
+                    cors,
+                ),
+                (Ok(name), stream) => {
+                    if let Err(e) = manager.status(&name) {
+                        respond(request, e.into(), cors);
+                    } else {
+                        let writer = request.into_writer();
+                        stream_logs(manager, &name, stream == "stderr", writer, cors);
+                    }
+                }
+            }
+            return;
+        }
+        _ => {}
+    }
+
+    let mut body = String::new();
+    if request.as_reader().read_to_string(&mut body).is_err() {
+        respond(request, Reply::error(400, "failed to read request body"), cors);
+        return;
+    }
+    let reply = match route(manager, &method, &segments, query, &body) {
+        Some(reply) => reply,
+        None => match static_dir {
+            Some(dir) if method == Method::Get => {
+                serve_static(request, dir, &segments);
+                return;
+            }
+            _ => Reply::error(404, "Not Found"),
+        },
+    };
+    respond(request, reply, cors);
+}
+
+fn route(
+    manager: &Manager,
+    method: &Method,
+    segments: &[&str],
+    query: &str,
+    body: &str,
+) -> Option<Reply> {
+    let reply = match (method, segments) {
+        (Method::Get, ["apps-info"]) => apps_info(manager),
+        (Method::Get, ["system-info"]) => system_info(manager).map(Reply::ok),
+        (Method::Post, ["create-app"]) => create_app(manager, body),
+        (Method::Post, ["apps", name, "run"]) => parse_name(name).and_then(|name| {
+            let info = manager.run(&name, Origin::Api, RunOptions::default())?;
+            let dependencies: Vec<&str> = info.dependencies.iter().map(|d| d.name.as_str()).collect();
+            Ok(Reply::ok(json!({
+                "message": format!("Started '{}'", name),
+                "pid": info.pid,
+                "started_at": info.started_at,
+                "dependencies": dependencies,
+            })))
+        }),
+        (Method::Post, ["apps", name, "stop"]) => parse_name(name).and_then(|name| {
+            let flag = |key| query_param(query, key).is_some_and(|value| value == "true");
+            let stopped = manager.stop(
+                &name,
+                StopOptions {
+                    force: flag("force"),
+                    cascade: flag("cascade"),
+                    capture: true,
+                    ..StopOptions::default()
+                },
+            )?;
+            // null when stop.sh didn't run
+            let script = stopped.script.as_ref();
+            Ok(Reply::ok(json!({
+                "exit_code": script.and_then(|script| script.exit_code),
+                "stdout": script.map(|script| &script.stdout),
+                "stderr": script.map(|script| &script.stderr),
+                "stopped_by": stopped.stopped.to_string(),
+                "dependents": stopped.dependents,
+                "running_dependents": stopped.running_dependents,
+            })))
+        }),
+        (Method::Post, ["apps", name, "kill"]) => {
+            parse_name(name).and_then(|name| match manager.kill(&name, false)?.killed {
+                Killed::Pid(pid) => Ok(Reply::message(format!(
+                    "Force killed '{}' (pid {})",
+                    name, pid
+                ))),
+                Killed::CancelledRestart => Ok(Reply::message(format!(
+                    "Cancelled pending restart of '{}'",
+                    name
+                ))),
+            })
+        }
+        (Method::Delete, ["apps", name]) => parse_name(name).and_then(|name| {
+            let force = query_param(query, "force").is_some_and(|value| value == "true");
+            manager.delete(&name, force)?;
+            Ok(Reply::message(format!("Deleted app '{}'", name)))
+        }),
+        (Method::Post, ["apps", name, "rename"]) => parse_name(name).and_then(|name| {
+            let new_name = parse_name(json_field(body, "newName")?.trim())?;
+            manager.rename(&name, &new_name)?;
+            Ok(Reply::message(format!(
+                "Renamed app '{}' to '{}'",
+                name, new_name
+            )))
+        }),
+        (Method::Get, ["apps", name, "scripts"]) => parse_name(name).and_then(|name| {
+            let scripts = manager.scripts(&name)?;
+            Ok(Reply::ok(json!({
+                "startScript": scripts.start,
+                "stopScript": scripts.stop,
+            })))
+        }),
+        (Method::Put, ["apps", name, "scripts"]) => parse_name(name).and_then(|name| {
+            let scripts = Scripts {
+                start: json_field(body, "startScript")?,
+                stop: json_field(body, "stopScript")?,
+            };
+            manager.save_scripts(&name, &scripts)?;
+            Ok(Reply::message(format!("Scripts saved for '{}'", name)))
+        }),
+        (Method::Get, ["apps", name, "logs"]) => parse_name(name).and_then(|name| {
+            let mut logs = json!({ "stdout": [], "stderr": [] });
+            for stream in ["stdout", "stderr"] {
+                let query = LogQuery {
+                    stdout: stream == "stdout",
+                    stderr: stream == "stderr",
+                    ..LogQuery::default()
+                };
+                match manager.logs(&name, &query) {
+                    Ok(lines) => {
+                        let lines: Vec<String> = lines.into_iter().map(|line| line.text).collect();
+                        logs[stream] = json!(lines);
+                    }
+                    // not run yet
+                    Err(Error::Failed(_)) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            Ok(Reply::ok(logs))
+        }),
+        (Method::Post, ["apps", name, "logs", "clear"]) => parse_name(name).and_then(|name| {
+            manager.clear_logs(&name, true, true)?;
+            Ok(Reply::message(format!("Logs cleared for '{}'", name)))
+        }),
+        (Method::Post, ["apps", name, "open"]) => parse_name(name).and_then(|name| {
+            let path = manager.status(&name)?.path;
+            let mut child = process::Command::new("xdg-open")
+                .arg(&path)
+                .spawn()
+                .map_err(|e| {
+                    Reply::error(
+                        500,
+                        format!("Failed to open folder (is xdg-open installed?): {}", e),
+                    )
+                })?;
+            thread::spawn(move || child.wait());
+            Ok(Reply::message(format!(
+                "Opening folder: {}",
+                path.display()
+            )))
+        }),
+        _ => return None,
+    };
+    Some(reply.unwrap_or_else(|reply| reply))
+}
+
+/// The app object of the web UI
+fn app_info(status: &AppStatus) -> Value {
+    json!({
+        "name": status.name,
+        "path": status.path,
+        "pid": status.pid,
+        "runs": status.app.num_runs,
+        "uptime": status.uptime_secs,
+        "created": status.app.created_at,
+        "lastRun": status.app.last_run.as_ref().map(|last_run| &last_run.time),
+        "running": status.running,
+        "health": status.health,
+        "configError": status.config_error,
+    })
+}
+
+fn apps_info(manager: &Manager) -> Result<Reply, Reply> {
+    let apps: Vec<Value> = manager.list()?.iter().map(app_info).collect();
+    Ok(Reply::ok(json!(apps)))
+}
+
+fn system_info(manager: &Manager) -> Result<Value, Reply> {
+    let info = manager.info()?;
+    Ok(json!({
+        "baseDir": info.basedir,
+        "apps": info.apps,
+        "running": info.running,
+        "binary": info.binary,
+        "rustCrate": env!("CARGO_PKG_NAME"),
+        "version": info.version,
+    }))
+}
+
+fn create_app(manager: &Manager, body: &str) -> Result<Reply, Reply> {
+    let name = parse_name(json_field(body, "name")?.trim())?;
+    let status = manager.create(&name)?;
+    Ok(Reply::ok(app_info(&status)))
+}
+
+fn parse_name(name: &str) -> Result<AppName, Reply> {
+    name.parse().map_err(|e: String| Reply::error(400, e))
+}
+
+fn json_field(body: &str, field: &str) -> Result<String, Reply> {
+    let value: Value = serde_json::from_str(body)
+        .map_err(|e| Reply::error(400, format!("invalid json: {}", e)))?;
+    match value.get(field) {
+        Some(Value::String(value)) => Ok(value.clone()),
+        _ => Err(Reply::error(400, format!("missing field '{}'", field))),
+    }
+}
+
+fn query_param(query: &str, key: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, value)| percent_decode(value))
+}
+
+/// The percent-decoded, non-empty segments of a URL path
+fn url_segments(path: &str) -> Vec<String> {
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(percent_decode)
+        .collect()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("valid header")
+}
+
+fn header_value(request: &Request, name: &'static str) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.to_string())
+}
+
+/// Allows the `cors` origin, if any
+fn with_cors<R: Read>(response: Response<R>, cors: Option<&str>) -> Response<R> {
+    match cors {
+        Some(origin) => response
+            .with_header(header("Access-Control-Allow-Origin", origin))
+            .with_header(header("Vary", "Origin")),
+        None => response.with_header(header("Vary", "Origin")),
+    }
+}
+
+fn respond(request: Request, reply: Reply, cors: Option<&str>) {
+    let response = Response::from_string(reply.body.to_string())
+        .with_status_code(reply.status)
+        .with_header(header("Content-Type", "application/json"));
+    request.respond(with_cors(response, cors)).ok();
+}
+
+fn serve_static(request: Request, dir: &Path, segments: &[&str]) {
+    let Some(path) = static_path(dir, segments) else {
+        respond(request, Reply::error(404, "Not Found"), None);
+        return;
+    };
+    let content_type = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript",
+        Some("css") => "text/css",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("ico") => "image/x-icon",
+        Some("woff2") => "font/woff2",
+        Some("txt") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    };
+    match std::fs::File::open(&path) {
+        Ok(file) => {
+            let response =
+                Response::from_file(file).with_header(header("Content-Type", content_type));
+            request.respond(response).ok();
+        }
+        Err(_) => respond(request, Reply::error(404, "Not Found"), None),
+    }
+}
+
+/// The file under `dir` a GET of `segments` serves, `None` for anything
+/// outside of it. Segments are decoded, so `%2F` can't sneak in a separator.
+fn static_path(dir: &Path, segments: &[&str]) -> Option<PathBuf> {
+    if segments.iter().any(|segment| {
+        segment.starts_with('.')
+            || segment.contains(['/', '\\'])
+            || Path::new(segment).is_absolute()
+    }) {
+        return None;
+    }
+    let mut path = dir.to_path_buf();
+    path.extend(segments);
+    // client side routes of the frontend all load index.html
+    if !path.is_file() {
+        path = dir.join("index.html");
+    }
+    let path = path.canonicalize().ok()?;
+    path.starts_with(dir.canonicalize().ok()?).then_some(path)
+}
+
+/// Writes the headers of a server-sent events response
+fn start_events(writer: &mut dyn Write, cors: Option<&str>) -> std::io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n"
+    )?;
+    if let Some(origin) = cors {
+        write!(writer, "Access-Control-Allow-Origin: {}\r\n", origin)?;
+    }
+    write!(writer, "Vary: Origin\r\nConnection: close\r\n\r\n")?;
+    writer.flush()
+}
+
+fn send_event(writer: &mut dyn Write, data: &str) -> std::io::Result<()> {
+    write!(writer, "data: {}\n\n", data)?;
+    writer.flush()
+}
+
+/// `/events`: the app list and system info every two seconds
+fn stream_events(manager: &Manager, mut writer: Box<dyn Write + Send>, cors: Option<&str>) {
+    if start_events(&mut writer, cors).is_err() {
+        return;
+    }
+    loop {
+        let payload = match (apps_info(manager), system_info(manager)) {
+            (Ok(apps), Ok(system)) => json!({ "apps": apps.body, "system": system }),
+            (Err(reply), _) | (_, Err(reply)) => json!({ "error": reply.body["detail"] }),
+        };
+        if send_event(&mut writer, &payload.to_string()).is_err() {
+            return;
+        }
+        thread::sleep(EVENTS_INTERVAL);
+    }
+}
+
+/// `/apps/{name}/logs/stream`: the tail of one log, then every new line
+fn stream_logs(
+    manager: &Manager,
+    name: &AppName,
+    stderr: bool,
+    mut writer: Box<dyn Write + Send>,
+    cors: Option<&str>,
+) {
+    if start_events(&mut writer, cors).is_err() {
+        return;
+    }
+    let query = LogQuery {
+        stdout: !stderr,
+        stderr,
+        ..LogQuery::default()
+    };
+    let mut last_write = Instant::now();
+    let result = manager.follow_logs(name, &query, STREAM_TAIL, false, |lines| {
+        let mut result = Ok(());
+        for line in lines {
+            result = result.and_then(|_| send_event(&mut writer, line.text.trim_end_matches('\r')));
+            last_write = Instant::now();
+        }
+        if last_write.elapsed() > KEEPALIVE_INTERVAL {
+            result = result.and_then(|_| {
+                writer.write_all(b": keepalive\n\n")?;
+                writer.flush()
+            });
+            last_write = Instant::now();
+        }
+        result.is_ok()
+    });
+    if result.is_err() {
+        send_event(
+            &mut writer,
+            "[Log file not found. App may not have run yet.]",
+        )
+        .ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::temp_dir;
+
+    fn static_file(dir: &Path, url: &str) -> Option<PathBuf> {
+        let segments = url_segments(url);
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+        static_path(dir, &segments)
+    }
+
+    #[test]
+    fn static_path_serves_files_and_falls_back_to_index() {
+        let dir = temp_dir("static");
+        std::fs::create_dir_all(dir.join("assets")).unwrap();
+        std::fs::write(dir.join("index.html"), "index").unwrap();
+        std::fs::write(dir.join("assets").join("app.js"), "js").unwrap();
+        let dir = dir.canonicalize().unwrap();
+        assert_eq!(
+            static_file(&dir, "/assets/app.js"),
+            Some(dir.join("assets").join("app.js"))
+        );
+        assert_eq!(static_file(&dir, "/apps/web"), Some(dir.join("index.html")));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn static_path_stays_in_the_static_dir() {
+        let dir = temp_dir("static-escape");
+        std::fs::create_dir_all(dir.join("assets")).unwrap();
+        std::fs::write(dir.join("index.html"), "index").unwrap();
+        for url in [
+            "/%2Fetc%2Fhostname",
+            "/assets%2F..%2F..%2F..%2Fetc%2Fhostname",
+            "/assets/..%2F..%2Fetc%2Fhostname",
+            "/..",
+            "/assets/%5C..%5Cindex.html",
+        ] {
+            assert_eq!(static_file(&dir, url), None, "{} was served", url);
+        }
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn allowed_hosts_of_the_bind_address() {
+        assert_eq!(
+            allowed_hosts("0.0.0.0:8000"),
+            ["0.0.0.0:8000", "localhost:8000", "127.0.0.1:8000"]
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+
+use crate::app::{AppName, HealthState, LastRunInfo, Origin, RunRecord};
+use crate::config::read_config;
+use crate::error::Error;
+use crate::health;
+use crate::history::append_history;
+use crate::logs::{capture_log, remove_segments};
+use crate::procfs;
+use crate::utils::{
+    archive_logs, check_running, hold_lock, log_paths, read_app, spawn_detached, update_app,
+};
+
+/// Entry point of the hidden `supervise` command spawned by `run`.
+///
+/// Starts the app, waits for it to exit and restarts it according to its
+/// restart policy. The pid of the first run (or `error: <exit code> <message>`
+/// for the error that prevented it) is written to stdout as a single line so
+/// `run` can report it.
+pub fn supervise(basedir: &Path, name: &AppName, origin: Origin) -> Result<(), Error> {
+    supervise_to(basedir, name, origin, std::io::stdout())
+}
+
+/// Like `supervise`, writing the pid line to `handshake` instead of stdout.
+/// The daemon runs this on a thread, so it is the parent of the app.
+pub fn supervise_to(
+    basedir: &Path,
+    name: &AppName,
+    origin: Origin,
+    handshake: impl Write,
+) -> Result<(), Error> {
+    let mut handshake = Some(handshake);
+    let result = supervise_loop(basedir, name, origin, &mut handshake);
+    if let (Err(e), Some(mut out)) = (&result, handshake) {
+        let message = e.to_string().replace('\n', " ");
+        writeln!(out, "error: {} {}", e.exit_code(), message).ok();
+    }
+    result
+}
+
+fn supervise_loop(
+    basedir: &Path,
+    name: &AppName,
+    origin: Origin,
+    handshake: &mut Option<impl Write>,
+) -> Result<(), Error> {
+    let path = basedir.join(name);
+    let script = path.join("start.sh");
+
+    // held for as long as this supervisor lives
+    let _lock = hold_lock(&path.join("supervisor.lock"))
+        .map_err(|_| Error::AlreadyRunning {
+            name: name.to_string(),
+            pid: None,
+        })?;
+
+    let mut started_by = origin;
+    loop {
+        if check_running(&path)? {
+            return Err(Error::AlreadyRunning {
+                name: name.to_string(),
+                pid: None,
+            });
+        }
+
+        let config = read_config(&path)?;
+        let num_runs = read_app(&path)?.num_runs;
+        let logs = log_paths(&path)?;
+        if config.logs.keep_runs > 0 && num_runs > 0 {
+            archive_logs(&path, num_runs, config.logs.keep_runs)?;
+        } else {
+            // the logs get truncated, their rotated segments go with them
+            remove_segments(&logs.stdout)?;
+            remove_segments(&logs.stderr)?;
+        }
+
+        let mut child = spawn_detached(&script, &path, &config)?;
+        let started = Instant::now();
+
+        let mut copiers = Vec::new();
+        if let Some(stdout) = child.stdout.take() {
+            copiers.push(capture_log(stdout, logs.stdout, config.logs.clone())?);
+        }
+        if let Some(stderr) = child.stderr.take() {
+            copiers.push(capture_log(stderr, logs.stderr, config.logs.clone())?);
+        }
+
+        update_app(&path, |app| {
+            app.last_run = Some(LastRunInfo {
+                time: Local::now().to_rfc3339(),
+                pid: child.id(),
+                start_time: procfs::start_time(child.id()),
+                started_by,
+                exit_code: None,
+                signal: None,
+                ended_at: None,
+            });
+            app.num_runs += 1;
+            app.restarts.next_retry_at = None;
+            app.health_state = HealthState::default();
+            Ok(())
+        })?;
+        let done = Arc::new(AtomicBool::new(false));
+        let monitor = health::monitor(path.clone(), child.id(), done.clone());
+
+        if let Some(mut out) = handshake.take() {
+            writeln!(out, "{}", child.id()).ok();
+        }
+
+        let status = child
+            .wait()
+            .map_err(|e| Error::io(format!("failed to wait for app '{}'", name), e))?;
+        done.store(true, Ordering::Relaxed);
+        // true when the health check stopped the app
+        let unhealthy = monitor.join().unwrap_or(false);
+
+        update_app(&path, |app| {
+            if let Some(last_run) = app.last_run.as_mut() {
+                last_run.exit_code = status.code();
+                last_run.signal = status.signal();
+                last_run.ended_at = Some(Local::now().to_rfc3339());
+                append_history(&path, &RunRecord::new(app.num_runs, last_run))?;
+            }
+            Ok(())
+        })?;
+
+        // flush captured output, the pipes close once every process holding them is gone
+        for copier in copiers {
+            copier.join().ok();
+        }
+
+        // picks up policy changes made while the app ran
+        let policy = read_config(&path)?.restart;
+        let retry = update_app(&path, |app| {
+            if started.elapsed().as_secs() >= policy.reset_after_secs {
+                app.restarts.count = 0;
+            }
+            let restart = if unhealthy {
+                policy.can_retry(app.restarts.count)
+            } else {
+                policy.should_restart(status.success(), app.restarts.count)
+            };
+            if app.restarts.stop_requested || !restart {
+                app.restarts.next_retry_at = None;
+                return Ok(None);
+            }
+
+            let delay = Duration::from_secs(policy.backoff(app.restarts.count));
+            app.restarts.count += 1;
+            app.restarts.next_retry_at = Some(
+                (Local::now() + chrono::Duration::from_std(delay).unwrap_or_default())
+                    .to_rfc3339(),
+            );
+            Ok(Some(delay))
+        })?;
+        let Some(delay) = retry else {
+            return Ok(());
+        };
+        started_by = Origin::Restart;
+
+        // wait out the backoff, giving stop/kill a chance to cancel it
+        let deadline = Instant::now() + delay;
+        while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+            thread::sleep(left.min(Duration::from_millis(200)));
+            if read_app(&path)?.restarts.stop_requested {
+                return update_app(&path, |app| {
+                    app.restarts.next_retry_at = None;
+                    Ok(())
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,9 @@
+use std::path::PathBuf;
+
+/// A fresh, empty dir under the system temp dir for one test
+pub fn temp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("execmgr-{}-{}", test, std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
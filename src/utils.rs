@@ -1,24 +1,191 @@
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
 use fs2::FileExt;
 use std::{
-    fs::{OpenOptions, create_dir_all},
-    path::PathBuf,
+    fs::{File, OpenOptions, create_dir_all},
+    io::Read,
+    os::unix::{io::AsRawFd, process::CommandExt},
+    path::{Path, PathBuf},
     process::{self},
+    sync::{Arc, Mutex, mpsc},
+    thread,
+    time::{Duration, Instant},
 };
+
+use crate::app::{App, LastRunInfo, SIGNALS, signal_name};
+use crate::config::AppConfig;
+use crate::env::env_map;
+use crate::error::Error;
+use crate::logs::{rotated_segments, segment_path};
+use crate::procfs;
 pub struct LogPath {
     pub stdout: PathBuf,
     pub stderr: PathBuf,
 }
-pub fn log_paths(app_dir: &PathBuf) -> Result<LogPath, String> {
+pub fn log_paths(app_dir: &Path) -> Result<LogPath, Error> {
     let log_dir = app_dir.join("logs");
     create_dir_all(&log_dir)
-        .map_err(|e| format!("failed to create log dir {:?}: {}", log_dir, e))?;
+        .map_err(|e| Error::io(format!("failed to create log dir {:?}", log_dir), e))?;
     Ok(LogPath {
         stdout: log_dir.join("stdout.log"),
         stderr: log_dir.join("stderr.log"),
     })
 }
 
+/// Log files of an archived run, `logs/runs/<run>-stdout.log`
+pub fn run_log_paths(app_dir: &Path, run: u64) -> LogPath {
+    let runs_dir = app_dir.join("logs").join("runs");
+    LogPath {
+        stdout: runs_dir.join(format!("{}-stdout.log", run)),
+        stderr: runs_dir.join(format!("{}-stderr.log", run)),
+    }
+}
+
+/// Moves the logs of `run` into logs/runs and drops archives older than the last `keep` runs
+pub fn archive_logs(app_dir: &Path, run: u64, keep: u32) -> Result<(), Error> {
+    let logs = log_paths(app_dir)?;
+    let runs_dir = app_dir.join("logs").join("runs");
+    create_dir_all(&runs_dir)
+        .map_err(|e| Error::io(format!("failed to create log dir {:?}", runs_dir), e))?;
+
+    let archived = run_log_paths(app_dir, run);
+    for (from, to) in [
+        (&logs.stdout, &archived.stdout),
+        (&logs.stderr, &archived.stderr),
+    ] {
+        // rotated segments move along with the log they belong to
+        for (index, segment) in rotated_segments(from).iter().rev().enumerate() {
+            let compressed = segment.extension().is_some_and(|ext| ext == "gz");
+            let target = segment_path(to, index as u32 + 1, compressed);
+            std::fs::rename(segment, &target)
+                .map_err(|e| Error::io(format!("failed to archive {:?}", segment), e))?;
+        }
+        if from.exists() {
+            std::fs::rename(from, to)
+                .map_err(|e| Error::io(format!("failed to archive {:?}", from), e))?;
+        }
+    }
+
+    let entries: Vec<(u64, PathBuf)> = std::fs::read_dir(&runs_dir)
+        .map_err(|e| Error::io(format!("unable to read {:?}", runs_dir), e))?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let run = entry.file_name().to_str()?.split_once('-')?.0.parse().ok()?;
+            Some((run, entry.path()))
+        })
+        .collect();
+    let mut runs: Vec<u64> = entries.iter().map(|(run, _)| *run).collect();
+    runs.sort_unstable();
+    runs.dedup();
+    let oldest_kept = runs
+        .len()
+        .checked_sub(keep as usize)
+        .and_then(|i| runs.get(i))
+        .copied()
+        .unwrap_or(0);
+    for (run, file) in entries {
+        if run < oldest_kept {
+            std::fs::remove_file(&file)
+                .map_err(|e| Error::io(format!("failed to remove {:?}", file), e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Parses sizes like `512`, `64K`, `10M` or `1G` into bytes
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let (number, unit) = match size.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => size.split_at(i),
+        None => (size, ""),
+    };
+    let multiplier: u64 = match unit.to_ascii_uppercase().trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(format!("invalid size unit '{}' (use K, M or G)", unit)),
+    };
+    number
+        .parse::<u64>()
+        .map_err(|_| format!("invalid size '{}'", size))?
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size '{}' is too large", size))
+}
+
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        0 => "-".into(),
+        b if b % (1 << 30) == 0 => format!("{}G", b >> 30),
+        b if b % (1 << 20) == 0 => format!("{}M", b >> 20),
+        b if b % (1 << 10) == 0 => format!("{}K", b >> 10),
+        b => format!("{}B", b),
+    }
+}
+
+/// Formats a command line for display, quoting arguments the shell would split or expand
+pub fn shell_join(argv: &[String]) -> String {
+    argv.iter()
+        .map(|arg| {
+            let plain = !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./=:,@%+".contains(c));
+            if plain {
+                arg.clone()
+            } else {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Parses a point in time given as RFC3339, `YYYY-MM-DD[ HH:MM[:SS]]` (local time)
+/// or a duration ago such as `90s`, `10m`, `2h` or `1d`
+pub fn parse_time(time: &str) -> Result<DateTime<Local>, String> {
+    let time = time.trim();
+    let invalid = || {
+        format!(
+            "invalid time '{}' (use RFC3339, 'YYYY-MM-DD HH:MM:SS' or a duration like 10m)",
+            time
+        )
+    };
+
+    // unsigned, a negative duration would be in the future
+    if let Some(unit) = time.chars().last().filter(|c| "smhd".contains(*c))
+        && let Ok(amount) = time[..time.len() - 1].parse::<u64>()
+    {
+        let multiplier = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            _ => 60 * 60 * 24,
+        };
+        return i64::try_from(amount)
+            .ok()
+            .and_then(|amount| amount.checked_mul(multiplier))
+            .and_then(chrono::TimeDelta::try_seconds)
+            .and_then(|ago| Local::now().checked_sub_signed(ago))
+            .ok_or_else(|| format!("duration '{}' is too long", time));
+    }
+    if let Ok(t) = DateTime::parse_from_rfc3339(time) {
+        return Ok(t.with_timezone(&Local));
+    }
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(time, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(time, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .ok_or_else(invalid)?;
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .ok_or_else(invalid)
+}
+
 pub fn since_running(started_at: &str) -> Option<i64> {
     let start: DateTime<Local> = match started_at.parse() {
         Ok(t) => t,
@@ -31,10 +198,57 @@ pub fn since_running(started_at: &str) -> Option<i64> {
     Some(dur.num_seconds())
 }
 
+pub fn read_app(app_dir: &Path) -> Result<App, Error> {
+    let json_path = app_dir.join("app.json");
+    let content = std::fs::read_to_string(&json_path)
+        .map_err(|e| Error::io(format!("unable to read {:?}", json_path), e))?;
+    serde_json::from_str(&content).map_err(|e| Error::CorruptMetadata {
+        path: json_path,
+        reason: e.to_string(),
+    })
+}
+
+/// Writes app.json atomically, changes to an existing one go through `update_app`
+pub fn write_app(app_dir: &Path, app: &App) -> Result<(), Error> {
+    let json_path = app_dir.join("app.json");
+    let tmp_path = app_dir.join("app.json.tmp");
+    let app_json = serde_json::to_string_pretty(app)
+        .map_err(|e| Error::Failed(format!("failed to dump app json: {}", e)))?;
+    std::fs::write(&tmp_path, app_json)
+        .map_err(|e| Error::io(format!("failed to write {:?}", tmp_path), e))?;
+    std::fs::rename(&tmp_path, &json_path)
+        .map_err(|e| Error::io(format!("failed to write {:?}", json_path), e))
+}
+
+/// Read-modify-write of app.json under an exclusive lock on app.json.lock, so
+/// the cli, the supervisor and the health monitor don't overwrite each other's
+/// changes. Nothing is written when `update` fails.
+pub fn update_app<T>(
+    app_dir: &Path,
+    update: impl FnOnce(&mut App) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let lock_path = app_dir.join("app.json.lock");
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(|e| Error::io(format!("unable to open {:?}", lock_path), e))?;
+    lock.lock_exclusive()
+        .map_err(|e| Error::io(format!("unable to lock {:?}", lock_path), e))?;
+    let mut app = read_app(app_dir)?;
+    let result = update(&mut app)?;
+    write_app(app_dir, &app)?;
+    Ok(result)
+}
+
 /// Returns true if the app lock is currently held by another process
-pub fn is_lock_held(app_dir: &PathBuf) -> Result<bool, String> {
-    let lock_path = app_dir.join("app.lock");
+pub fn is_lock_held(app_dir: &Path) -> Result<bool, Error> {
+    is_file_lock_held(&app_dir.join("app.lock"))
+}
 
+/// Returns true if the lock at `lock_path` is currently held by another process
+pub fn is_file_lock_held(lock_path: &Path) -> Result<bool, Error> {
     if !lock_path.exists() {
         return Ok(false);
     }
@@ -42,8 +256,8 @@ pub fn is_lock_held(app_dir: &PathBuf) -> Result<bool, String> {
     let file = OpenOptions::new()
         .read(true)
         .write(true)
-        .open(&lock_path)
-        .map_err(|e| format!("failed to open lockfile {:?}: {}", lock_path, e))?;
+        .open(lock_path)
+        .map_err(|e| Error::io(format!("failed to open lockfile {:?}", lock_path), e))?;
 
     // acquire exclusive lock
     match file.try_lock_exclusive() {
@@ -52,75 +266,359 @@ pub fn is_lock_held(app_dir: &PathBuf) -> Result<bool, String> {
     } // drop lock
 }
 
-pub fn spawn_detached(script: &PathBuf, app_dir: &PathBuf) -> Result<u32, String> {
-    let logs = log_paths(app_dir)?;
-
-    let stdout = OpenOptions::new()
+/// Takes the lock at `lock_path` and keeps it until the returned file is dropped
+pub fn hold_lock(lock_path: &Path) -> Result<File, Error> {
+    let file = OpenOptions::new()
         .create(true)
+        .truncate(false)
+        .read(true)
         .write(true)
-        .truncate(true)
-        .open(logs.stdout)
-        .map_err(|e| format!("failed to open stdout log: {}", e))?;
+        .open(lock_path)
+        .map_err(|e| Error::io(format!("failed to open lockfile {:?}", lock_path), e))?;
+    file.try_lock_exclusive()
+        .map_err(|e| Error::io(format!("lock {:?} is held by another process", lock_path), e))?;
+    Ok(file)
+}
 
-    let stderr = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(logs.stderr)
-        .map_err(|e| format!("failed to open stderr log: {}", e))?;
+/// Spawns start.sh (or the configured command) holding the app lock. The lock
+/// is taken in the child right before exec on an fd that the app and everything
+/// it starts inherit, so the app counts as running until the last of them exits.
+/// When the log settings need capturing, the output is piped back to the caller
+/// instead of going straight into the log files.
+pub fn spawn_detached(
+    script: &Path,
+    app_dir: &Path,
+    config: &AppConfig,
+) -> Result<process::Child, Error> {
+    let (stdout, stderr) = if config.logs.capture() {
+        (process::Stdio::piped(), process::Stdio::piped())
+    } else {
+        let logs = log_paths(app_dir)?;
+
+        let stdout = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(logs.stdout)
+            .map_err(|e| Error::io("failed to open stdout log", e))?;
+
+        let stderr = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(logs.stderr)
+            .map_err(|e| Error::io("failed to open stderr log", e))?;
+
+        (process::Stdio::from(stdout), process::Stdio::from(stderr))
+    };
 
     let lockfile = app_dir.join("app.lock");
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lockfile)
+        .map_err(|e| Error::io(format!("failed to open lockfile {:?}", lockfile), e))?;
 
-    let cmd = format!(
-        r#"
-        set -e
-        exec 9>"{lock}"
-        if ! flock -n 9; then
-            echo "app already running" >&2
-            exit 1
-        fi
-        exec "{script}"
-        "#,
-        lock = lockfile.display(),
-        script = script.display(),
-    );
-
-    let child = process::Command::new("bash")
-        .arg("-c")
-        .arg(cmd)
+    // the script is named relative to the caller's directory, the app runs in its own
+    let script = std::path::absolute(script)
+        .map_err(|e| Error::io(format!("failed to resolve {:?}", script), e))?;
+    let (program, args) = match config.command.as_deref() {
+        Some([program, args @ ..]) => (Path::new(program), args),
+        _ => (script.as_path(), &[][..]),
+    };
+    let env = env_map(app_dir, config)?;
+    let mut command = process::Command::new(program);
+    command
+        .args(args)
+        .env_clear()
+        .envs(&env)
+        .current_dir(work_dir(app_dir, config)?)
         .stdin(process::Stdio::null())
-        .stdout(process::Stdio::from(stdout))
-        .stderr(process::Stdio::from(stderr))
-        .spawn()
-        .map_err(|e| format!("failed to spawn bash wrapper: {}", e))?;
+        .stdout(stdout)
+        .stderr(stderr);
+    // SAFETY: only async-signal-safe calls (setsid, flock, fcntl) run between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            // own session and process group (pgid = sid = pid), so the whole tree
+            // started by start.sh can be signalled at once
+            if libc::setsid() == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            lock.try_lock_exclusive()?;
+            // std opens files with O_CLOEXEC, the lock has to survive the exec
+            let fd = lock.as_raw_fd();
+            let flags = libc::fcntl(fd, libc::F_GETFD);
+            if flags == -1 || libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = command.spawn().map_err(|e| {
+        if e.kind() == std::io::ErrorKind::WouldBlock {
+            Error::AlreadyRunning {
+                name: app_dir
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                pid: None,
+            }
+        } else {
+            Error::io(format!("failed to spawn {:?}", program), e)
+        }
+    })?;
 
-    Ok(child.id())
+    Ok(child)
 }
 
+/// The configured working directory, which has to exist
+pub fn work_dir(app_dir: &Path, config: &AppConfig) -> Result<PathBuf, Error> {
+    let dir = config.work_dir(app_dir);
+    if !dir.is_dir() {
+        return Err(Error::Invalid(format!(
+            "working directory {:?} does not exist",
+            dir
+        )));
+    }
+    Ok(dir)
+}
+
+/// How long `run_captured` keeps reading after the script exited, output of
+/// processes it left behind is not waited for
+const CAPTURE_GRACE: Duration = Duration::from_millis(200);
+
+/// A script of the app, in its working directory and with its environment
+fn script_command(
+    script: &Path,
+    app_dir: &Path,
+    config: &AppConfig,
+) -> Result<process::Command, Error> {
+    let script = std::path::absolute(script)
+        .map_err(|e| Error::io(format!("failed to resolve {:?}", script), e))?;
+    let mut command = process::Command::new(script);
+    command
+        .env_clear()
+        .envs(env_map(app_dir, config)?)
+        .current_dir(work_dir(app_dir, config)?);
+    Ok(command)
+}
 
-pub fn run_attached(script: &PathBuf) -> Result<process::ExitStatus, String> {
-    let status = process::Command::new(script)
+/// Runs a script of the app in the foreground, in its working directory and
+/// with its environment
+pub fn run_attached(
+    script: &Path,
+    app_dir: &Path,
+    config: &AppConfig,
+) -> Result<process::ExitStatus, Error> {
+    let status = script_command(script, app_dir, config)?
         .stdin(process::Stdio::inherit())
         .stdout(process::Stdio::inherit())
         .stderr(process::Stdio::inherit())
         .status()
-        .map_err(|e| format!("failed to run: {e}"))?;
+        .map_err(|e| Error::io("failed to run", e))?;
 
     Ok(status)
 }
 
-pub fn kill_pid(pid: u32) -> Result<process::ExitStatus, String> {
-    // SIGKILL = -9 (cannot be ignored)
-    let status = std::process::Command::new("kill")
-        .arg("-9")
-        .arg(pid.to_string())
-        .status()
-        .map_err(|e| format!("failed to run kill -9: {}", e))?;
-    Ok(status)
+/// Like `run_attached`, returning the output instead of inheriting stdio
+pub fn run_captured(
+    script: &Path,
+    app_dir: &Path,
+    config: &AppConfig,
+) -> Result<process::Output, Error> {
+    let mut child = script_command(script, app_dir, config)?
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::io("failed to run", e))?;
+    let stdout = child.stdout.take().map(capture);
+    let stderr = child.stderr.take().map(capture);
+    let status = child.wait().map_err(|e| Error::io("failed to run", e))?;
+    let deadline = Instant::now() + CAPTURE_GRACE;
+    Ok(process::Output {
+        status,
+        stdout: stdout.map(|c| c.collect(deadline)).unwrap_or_default(),
+        stderr: stderr.map(|c| c.collect(deadline)).unwrap_or_default(),
+    })
+}
+
+/// Output of a pipe read on a thread, see `capture`
+struct Captured {
+    buffer: Arc<Mutex<Vec<u8>>>,
+    done: mpsc::Receiver<()>,
+}
+
+impl Captured {
+    /// What was read so far, after waiting for the end until `deadline`
+    fn collect(self, deadline: Instant) -> Vec<u8> {
+        self.done
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            .ok();
+        std::mem::take(&mut *self.buffer.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// Reads `reader` to the end on a thread
+fn capture(mut reader: impl Read + Send + 'static) -> Captured {
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let (done, receiver) = mpsc::channel();
+    let shared = buffer.clone();
+    thread::spawn(move || {
+        let mut chunk = [0; 8192];
+        while let Ok(n @ 1..) = reader.read(&mut chunk) {
+            shared
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .extend_from_slice(&chunk[..n]);
+        }
+        done.send(()).ok();
+    });
+    Captured {
+        buffer,
+        done: receiver,
+    }
+}
+
+pub fn send_signal(pid: u32, signal: i32) -> Result<(), Error> {
+    // SAFETY: kill(2) only takes plain integers
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(Error::io(
+            format!("failed to send {} to pid {}", signal_name(signal), pid),
+            std::io::Error::last_os_error(),
+        ))
+    }
+}
+
+/// Makes sure the recorded pid still belongs to the app before it is signalled:
+/// the process must have the start time recorded at spawn and the app lock
+/// must be held by that pid.
+pub fn verify_pid(app_dir: &Path, last_run: &LastRunInfo) -> Result<(), Error> {
+    let pid = last_run.pid;
+    // a pid that is gone cannot be reused while its process group is still alive
+    if let (Some(expected), Some(actual)) = (last_run.start_time, procfs::start_time(pid))
+        && expected != actual
+    {
+        return Err(Error::PidMismatch(format!(
+            "pid {} now belongs to another process (started at tick {}, the app started at tick {}); refusing to signal it",
+            pid, actual, expected
+        )));
+    }
+    // /proc/locks names the process that took the lock; once that process is
+    // gone the lock lives on through the fds its children inherited
+    let holders: Vec<u32> = procfs::lock_holders(&app_dir.join("app.lock"))
+        .into_iter()
+        .filter(|holder| procfs::read_stat(*holder).is_some())
+        .collect();
+    if !holders.is_empty() && !holders.contains(&pid) {
+        return Err(Error::PidMismatch(format!(
+            "app lock is held by pid {} but the recorded pid is {}; refusing to signal it",
+            holders
+                .iter()
+                .map(|pid| pid.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            pid
+        )));
+    }
+    Ok(())
+}
+
+/// Signals the process group led by `pid`. With `descendants` the processes
+/// found in /proc that escaped the group are signalled too.
+pub fn signal_app(app_dir: &Path, pid: u32, signal: i32, descendants: bool) -> Result<(), Error> {
+    let escaped = if descendants {
+        procfs::escaped_descendants(pid, &app_dir.join("app.lock"))
+    } else {
+        Vec::new()
+    };
+    // SAFETY: kill(2) only takes plain integers, a negative pid targets the group
+    let group = unsafe { libc::kill(-(pid as libc::pid_t), signal) } == 0;
+    let error = std::io::Error::last_os_error();
+    let mut signalled = 0;
+    for child in escaped {
+        if send_signal(child, signal).is_ok() {
+            signalled += 1;
+        }
+    }
+    if group || signalled > 0 {
+        return Ok(());
+    }
+    // apps started before execmgr used process groups are not group leaders
+    send_signal(pid, signal).map_err(|_| {
+        Error::Failed(format!(
+            "failed to send {} to process group {}: {}{}",
+            signal_name(signal),
+            pid,
+            error,
+            if descendants {
+                ""
+            } else {
+                " (a process that left the group may still hold the app lock, retry with --tree)"
+            }
+        ))
+    })
+}
+
+/// Parses a signal given as `TERM`, `SIGTERM`, `15` or `signal 15` (how
+/// `signal_name` writes signals without a name)
+pub fn parse_signal(signal: &str) -> Result<i32, String> {
+    let signal = signal.trim();
+    let number = signal.strip_prefix("signal ").unwrap_or(signal).trim();
+    if let Ok(number) = number.parse::<i32>() {
+        return if (1..=libc::SIGRTMAX()).contains(&number) {
+            Ok(number)
+        } else {
+            Err(format!("invalid signal number {}", number))
+        };
+    }
+    let name = signal.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    SIGNALS
+        .iter()
+        .find(|(_, known)| *known == name)
+        .map(|(number, _)| *number)
+        .ok_or_else(|| format!("unknown signal '{}'", signal))
+}
+
+/// Polls the app lock until it is released, returns false on timeout
+pub fn wait_stopped(app_dir: &Path, timeout: Duration) -> Result<bool, Error> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if !check_running(app_dir)? {
+            return Ok(true);
+        }
+        if Instant::now() >= deadline {
+            return Ok(false);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
 }
 
-pub fn check_running(app_dir: &PathBuf) -> bool {
-    is_lock_held(app_dir).expect("Error:")
+/// Polls the supervisor lock until it is released, returns false on timeout
+pub fn wait_unsupervised(app_dir: &Path, timeout: Duration) -> Result<bool, Error> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if !check_supervised(app_dir)? {
+            return Ok(true);
+        }
+        if Instant::now() >= deadline {
+            return Ok(false);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+pub fn check_running(app_dir: &Path) -> Result<bool, Error> {
+    is_lock_held(app_dir)
+}
+
+/// Returns true if a supervisor is alive for the app (running or waiting to restart it)
+pub fn check_supervised(app_dir: &Path) -> Result<bool, Error> {
+    is_file_lock_held(&app_dir.join("supervisor.lock"))
 }
 
 pub fn resolve_base_dir() -> PathBuf {
@@ -140,3 +638,108 @@ pub fn resolve_base_dir() -> PathBuf {
     // Absolute last resort
     PathBuf::from(".execmgr")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_units() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("64K"), Ok(64 << 10));
+        assert_eq!(parse_size("10m"), Ok(10 << 20));
+        assert_eq!(parse_size("10MB"), Ok(10 << 20));
+        assert_eq!(parse_size(" 1G "), Ok(1 << 30));
+    }
+
+    #[test]
+    fn parse_size_rejects_invalid_sizes() {
+        for size in ["", "K", "10X", "1.5M", "-1", "10 M", "99999999999999G"] {
+            assert!(parse_size(size).is_err(), "{:?} was accepted", size);
+        }
+    }
+
+    #[test]
+    fn parse_time_durations_are_in_the_past() {
+        let now = Local::now();
+        for (time, seconds) in [("90s", 90), ("10m", 600), ("2h", 7200), ("1d", 86400)] {
+            let ago = (now - parse_time(time).unwrap()).num_seconds();
+            assert!(
+                (seconds - 1..seconds + 5).contains(&ago),
+                "{} was {}s ago",
+                time,
+                ago
+            );
+        }
+    }
+
+    #[test]
+    fn parse_time_dates() {
+        let expected = Local.with_ymd_and_hms(2024, 3, 1, 12, 30, 5).unwrap();
+        assert_eq!(parse_time("2024-03-01 12:30:05"), Ok(expected));
+        assert_eq!(parse_time("2024-03-01T12:30:05"), Ok(expected));
+        assert_eq!(
+            parse_time("2024-03-01 12:30"),
+            Ok(Local.with_ymd_and_hms(2024, 3, 1, 12, 30, 0).unwrap())
+        );
+        assert_eq!(
+            parse_time("2024-03-01"),
+            Ok(Local.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap())
+        );
+        assert_eq!(
+            parse_time("2024-03-01T12:30:05Z").unwrap().timestamp(),
+            1709296205
+        );
+    }
+
+    #[test]
+    fn parse_time_rejects_out_of_range_and_negative_durations() {
+        for time in [
+            "-5m",
+            "9999999999d",
+            "99999999999999d",
+            "9223372036854775807s",
+            "99999999999999999999s",
+        ] {
+            assert!(parse_time(time).is_err(), "{:?} was accepted", time);
+        }
+    }
+
+    #[test]
+    fn parse_time_rejects_garbage() {
+        for time in ["", "soon", "10x", "m", "2024-13-01", "2024-03-01 25:00"] {
+            assert!(parse_time(time).is_err(), "{:?} was accepted", time);
+        }
+    }
+
+    #[test]
+    fn parse_signal_names_and_numbers() {
+        assert_eq!(parse_signal("TERM"), Ok(libc::SIGTERM));
+        assert_eq!(parse_signal("SIGKILL"), Ok(libc::SIGKILL));
+        assert_eq!(parse_signal("sigint"), Ok(libc::SIGINT));
+        assert_eq!(parse_signal(" hup "), Ok(libc::SIGHUP));
+        assert_eq!(parse_signal("10"), Ok(10));
+        assert_eq!(parse_signal("USR2"), Ok(libc::SIGUSR2));
+        assert!(parse_signal("SIGFOO").is_err());
+        assert!(parse_signal("").is_err());
+    }
+
+    #[test]
+    fn parse_signal_rejects_out_of_range_numbers() {
+        let above = (libc::SIGRTMAX() + 1).to_string();
+        for signal in ["0", "-9", "signal 0", &above, "99999999999"] {
+            assert!(parse_signal(signal).is_err(), "{:?} was accepted", signal);
+        }
+        assert_eq!(
+            parse_signal(&libc::SIGRTMAX().to_string()),
+            Ok(libc::SIGRTMAX())
+        );
+    }
+
+    #[test]
+    fn signal_names_parse_back() {
+        for signal in 1..=libc::SIGRTMAX() {
+            assert_eq!(parse_signal(&signal_name(signal)), Ok(signal));
+        }
+    }
+}